@@ -0,0 +1,88 @@
+/// An error raised when the memory bus is unable to complete an access.
+///
+/// Each variant holds the address of the access. Devices report addresses as
+/// offsets into their window, which the bus replaces with the full address.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum BusError {
+    /// Nothing is mapped at the address.
+    Unmapped(u16),
+    /// The address may only be read.
+    ReadOnly(u16),
+    /// The address may only be written.
+    WriteOnly(u16),
+    /// The device mapped at the address refused the access.
+    Rejected(u16),
+}
+
+impl BusError {
+    /// The address of the failed access.
+    pub fn address(self) -> u16 {
+        match self {
+            Self::Unmapped(address)
+            | Self::ReadOnly(address)
+            | Self::WriteOnly(address)
+            | Self::Rejected(address) => address,
+        }
+    }
+
+    /// The same error for an access at `address` instead.
+    pub fn at(self, address: u16) -> Self {
+        match self {
+            Self::Unmapped(_) => Self::Unmapped(address),
+            Self::ReadOnly(_) => Self::ReadOnly(address),
+            Self::WriteOnly(_) => Self::WriteOnly(address),
+            Self::Rejected(_) => Self::Rejected(address),
+        }
+    }
+}
+
+/// Represents a peripheral mapped into a window of the address space.
+///
+/// Offsets passed to a device are relative to the start of its window.
+pub trait Device: Send {
+    /// The name of this device, used to label its window.
+    fn name(&self) -> &str;
+
+    /// The number of addresses in this device's window.
+    fn size(&self) -> u16;
+
+    /// Read the byte at `offset`, which may change the state of the device.
+    fn read(&mut self, offset: u16) -> Result<u8, BusError>;
+
+    /// Write `value` to `offset`.
+    fn write(&mut self, offset: u16, value: u8) -> Result<(), BusError>;
+
+    /// Retrieve the byte at `offset` without any effect on the device, for
+    /// debuggers and instruction fetches, or `None` if that is not possible.
+    fn peek(&self, _offset: u16) -> Option<u8> {
+        None
+    }
+
+    /// Advance this device by `cycles` machine cycles, after the virtual
+    /// machine spends them on an instruction or interrupt. The returned
+    /// interrupt line, if any, is raised.
+    fn step(&mut self, _cycles: u32) -> Option<u8> {
+        None
+    }
+
+    /// Put this device back into the state it started in, when the virtual
+    /// machine is reset. Devices without any state of their own do nothing.
+    fn reset(&mut self) {}
+
+    /// Capture the state of this device, to be restored by
+    /// [`Device::load_state`] when a save state is loaded. Devices without any
+    /// state of their own save nothing.
+    fn save_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Restore the state captured by [`Device::save_state`], or return `None`
+    /// without changing anything if it isn't a valid state for this device.
+    fn load_state(&mut self, state: &[u8]) -> Option<()> {
+        if state.is_empty() {
+            Some(())
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use crate::{Opcode, ReadableMemory, Register};
+use std::convert::TryFrom;
+
+/// An error encountered while decoding an instruction.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DecodeError {
+    /// The byte does not represent any opcode.
+    IllegalOpcode(u8),
+    /// The byte at this address is out of the memory's bounds.
+    OutOfBounds(u16),
+    /// The number in a register operand does not represent any register.
+    IllegalRegister(u8),
+}
+
+/// The kinds of operand that may follow an opcode.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum OperandKind {
+    /// The opcode takes no operand.
+    None,
+    /// A one byte immediate value.
+    Imm8,
+    /// A two byte immediate value.
+    Imm16,
+    /// A one byte memory address.
+    Addr8,
+    /// A two byte memory address.
+    Addr16,
+    /// A one byte signed offset from the address of the next instruction.
+    Rel8,
+    /// A one byte pair of registers, with the number of the first register in
+    /// the high four bits and the second in the low four bits.
+    Registers,
+}
+
+impl OperandKind {
+    /// The number of bytes this kind of operand takes.
+    pub fn size(self) -> u16 {
+        match self {
+            Self::None => 0,
+            Self::Imm8 | Self::Addr8 | Self::Rel8 | Self::Registers => 1,
+            Self::Imm16 | Self::Addr16 => 2,
+        }
+    }
+}
+
+/// How a load or store instruction forms the address of the word it accesses.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AddressMode {
+    /// The instruction doesn't load or store through an address it forms.
+    None,
+    /// The address is the operand.
+    Direct,
+    /// The address is the operand plus the address RAM is mapped at, which
+    /// reaches the first 256 bytes of RAM, called the zero page.
+    ZeroPage,
+    /// The address is held in the `B` register.
+    Indirect,
+    /// The address is the operand plus the `B` register, wrapping around at
+    /// the end of the address space.
+    Indexed,
+    /// The address is held in the `B` register, which is then moved past the
+    /// word by adding `2` to it.
+    PostIncrement,
+}
+
+/// The operand that follows an opcode within an instruction.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Operand {
+    /// The opcode takes no operand.
+    None,
+    /// A one byte immediate value.
+    Imm8(u8),
+    /// A two byte immediate value.
+    Imm16(u16),
+    /// A one byte memory address.
+    Addr8(u8),
+    /// A two byte memory address.
+    Addr16(u16),
+    /// A one byte signed offset from the address of the next instruction.
+    Rel8(i8),
+    /// A pair of registers, where the first is the destination of a move.
+    Registers(Register, Register),
+}
+
+impl Operand {
+    /// The kind of this operand.
+    pub fn kind(self) -> OperandKind {
+        match self {
+            Self::None => OperandKind::None,
+            Self::Imm8(_) => OperandKind::Imm8,
+            Self::Imm16(_) => OperandKind::Imm16,
+            Self::Addr8(_) => OperandKind::Addr8,
+            Self::Addr16(_) => OperandKind::Addr16,
+            Self::Rel8(_) => OperandKind::Rel8,
+            Self::Registers(..) => OperandKind::Registers,
+        }
+    }
+
+    /// The value of this operand, zero-extended to two bytes. Relative offsets
+    /// are sign-extended instead, so they may be added to an address with
+    /// wrapping. Register pairs have the value of their encoded byte. Opcodes
+    /// without an operand have a value of `0`.
+    pub fn value(self) -> u16 {
+        match self {
+            Self::None => 0,
+            Self::Imm8(value) | Self::Addr8(value) => value as u16,
+            Self::Imm16(value) | Self::Addr16(value) => value,
+            Self::Rel8(offset) => offset as i16 as u16,
+            Self::Registers(first, second) => Self::register_pair(first, second) as u16,
+        }
+    }
+
+    // Build an operand of `kind` from its little-endian bytes
+    fn from_bytes(kind: OperandKind, bytes: [u8; 2]) -> Result<Self, DecodeError> {
+        Ok(match kind {
+            OperandKind::None => Self::None,
+            OperandKind::Imm8 => Self::Imm8(bytes[0]),
+            OperandKind::Imm16 => Self::Imm16(u16::from_le_bytes(bytes)),
+            OperandKind::Addr8 => Self::Addr8(bytes[0]),
+            OperandKind::Addr16 => Self::Addr16(u16::from_le_bytes(bytes)),
+            OperandKind::Rel8 => Self::Rel8(bytes[0] as i8),
+            OperandKind::Registers => Self::Registers(
+                Register::try_from(bytes[0] >> 4)?,
+                Register::try_from(bytes[0] & 0xF)?,
+            ),
+        })
+    }
+
+    // Encode a pair of registers into a byte
+    fn register_pair(first: Register, second: Register) -> u8 {
+        (first as u8) << 4 | second as u8
+    }
+}
+
+/// A single instruction: an opcode along with its operand.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Instruction {
+    opcode: Opcode,
+    operand: Operand,
+}
+
+impl Instruction {
+    /// Create an instruction, or `None` if the operand is not of the kind the
+    /// opcode takes.
+    pub fn new(opcode: Opcode, operand: Operand) -> Option<Self> {
+        if opcode.operand() == operand.kind() {
+            Some(Self { opcode, operand })
+        } else {
+            None
+        }
+    }
+
+    /// Decode the instruction starting at `address` within `memory`.
+    pub fn decode(memory: &impl ReadableMemory, address: u16) -> Result<Self, DecodeError> {
+        let read = |address: u16| {
+            memory
+                .byte(address)
+                .ok_or(DecodeError::OutOfBounds(address))
+        };
+
+        let opcode = Opcode::try_from(read(address)?)?;
+        let kind = opcode.operand();
+
+        // Operands are stored after the opcode
+        let mut bytes = [0; 2];
+        for (i, byte) in bytes.iter_mut().enumerate().take(kind.size() as usize) {
+            *byte = read(address.wrapping_add(1 + i as u16))?;
+        }
+
+        Ok(Self {
+            opcode,
+            operand: Operand::from_bytes(kind, bytes)?,
+        })
+    }
+
+    /// Encode this instruction into the bytes that decode back into it.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![self.opcode as u8];
+        match self.operand {
+            Operand::None => {}
+            Operand::Imm8(value) | Operand::Addr8(value) => bytes.push(value),
+            Operand::Imm16(value) | Operand::Addr16(value) => {
+                bytes.extend_from_slice(&value.to_le_bytes())
+            }
+            Operand::Rel8(offset) => bytes.push(offset as u8),
+            Operand::Registers(first, second) => bytes.push(Operand::register_pair(first, second)),
+        }
+        bytes
+    }
+
+    /// The opcode of this instruction.
+    pub fn opcode(&self) -> Opcode {
+        self.opcode
+    }
+
+    /// The operand of this instruction.
+    pub fn operand(&self) -> Operand {
+        self.operand
+    }
+
+    /// The number of bytes this instruction takes, including the opcode's
+    /// byte.
+    pub fn size(&self) -> u16 {
+        self.opcode.size()
+    }
+}
@@ -4,24 +4,276 @@
 //!
 //! The following opcodes are available:
 //!
-//! | Name   | Value    | Bytes | Description                                                              |
-//! |--------|----------|-------|--------------------------------------------------------------------------|
-//! | nop    | 00000000 | 1     | Do nothing this cycle                                                    |
-//! | lda16  | 00000001 | 3     | Load the value in the next two bytes to the `a` register                 |
-//! | ldb16  | 00000010 | 3     | Load the value in the next two bytes to the `b` register                 |
-//! | sta16  | 00000011 | 3     | Store the value in the `a` register to the address in the next two bytes |
-//! | stb16  | 00000100 | 3     | Store the value in the `b` register to the address in the next two bytes |
-//! | lda8   | 00000101 | 2     | Load the value in the next byte to the `a` register                      |
-//! | ldb8   | 00000110 | 2     | Load the value in the next byte to the `b` register                      |
-//! | sta8   | 00000111 | 2     | Store the value in the `a` register to the address in the next byte      |
-//! | stb8   | 00001000 | 2     | Store the value in the `b` register to the address in the next byte      |
+//! | Name    | Value    | Bytes | Cycles | Description                                                                  |
+//! |---------|----------|-------|--------|------------------------------------------------------------------------------|
+//! | nop     | 00000000 | 1     | 1      | Do nothing this cycle                                                        |
+//! | lda16   | 00000001 | 3     | 3      | Load the value in the next two bytes to the `a` register                     |
+//! | ldb16   | 00000010 | 3     | 3      | Load the value in the next two bytes to the `b` register                     |
+//! | sta16   | 00000011 | 3     | 5      | Store the value in the `a` register to the address in the next two bytes     |
+//! | stb16   | 00000100 | 3     | 5      | Store the value in the `b` register to the address in the next two bytes     |
+//! | lda8    | 00000101 | 2     | 2      | Load the value in the next byte to the `a` register                          |
+//! | ldb8    | 00000110 | 2     | 2      | Load the value in the next byte to the `b` register                          |
+//! | sta8    | 00000111 | 2     | 4      | Store the value in the `a` register to the zero page offset in the next byte |
+//! | stb8    | 00001000 | 2     | 4      | Store the value in the `b` register to the zero page offset in the next byte |
+//! | add     | 00001001 | 1     | 1      | Add `b` to `a`, storing the result in `a`                                    |
+//! | sub     | 00001010 | 1     | 1      | Subtract `b` from `a`, storing the result in `a`                             |
+//! | nega    | 00001011 | 1     | 1      | Negate the `a` register                                                      |
+//! | negb    | 00001100 | 1     | 1      | Negate the `b` register                                                      |
+//! | inca    | 00001101 | 1     | 1      | Increment the `a` register                                                   |
+//! | incb    | 00001110 | 1     | 1      | Increment the `b` register                                                   |
+//! | passa   | 00001111 | 1     | 1      | Update the ALU outputs with the value of the `a` register                    |
+//! | passb   | 00010000 | 1     | 1      | Update the ALU outputs with the value of the `b` register                    |
+//! | and     | 00010001 | 1     | 1      | Bitwise AND `a` with `b`, storing the result in `a`                          |
+//! | or      | 00010010 | 1     | 1      | Bitwise OR `a` with `b`, storing the result in `a`                           |
+//! | xor     | 00010011 | 1     | 1      | Bitwise XOR `a` with `b`, storing the result in `a`                          |
+//! | bitflpa | 00010100 | 1     | 1      | Flip every bit in the `a` register                                           |
+//! | bitflpb | 00010101 | 1     | 1      | Flip every bit in the `b` register                                           |
+//! | shftl   | 00010110 | 1     | 1      | Signed shift `a` left by `b` bits                                            |
+//! | shftr   | 00010111 | 1     | 1      | Signed shift `a` right by `b` bits                                           |
+//! | ushftl  | 00011000 | 1     | 1      | Unsigned shift `a` left by `b` bits                                          |
+//! | ushftr  | 00011001 | 1     | 1      | Unsigned shift `a` right by `b` bits                                         |
+//! | rotl    | 00011010 | 1     | 1      | Rotate `a` left by `b` bits                                                  |
+//! | rotr    | 00011011 | 1     | 1      | Rotate `a` right by `b` bits                                                 |
+//! | jmp     | 00011100 | 3     | 3      | Jump to the address in the next two bytes                                    |
+//! | jmprel  | 00011101 | 2     | 2      | Jump by the signed offset in the next byte, from the end of this instruction |
+//! | jmpz    | 00011110 | 3     | 3      | Jump to the address in the next two bytes if `zero` is set                   |
+//! | jmpnz   | 00011111 | 3     | 3      | Jump to the address in the next two bytes if `zero` is not set               |
+//! | jmpc    | 00100000 | 3     | 3      | Jump to the address in the next two bytes if `carry_out` is set              |
+//! | jmpnc   | 00100001 | 3     | 3      | Jump to the address in the next two bytes if `carry_out` is not set          |
+//! | jmpn    | 00100010 | 3     | 3      | Jump to the address in the next two bytes if `negative` is set               |
+//! | jmpnn   | 00100011 | 3     | 3      | Jump to the address in the next two bytes if `negative` is not set           |
+//! | jmpo    | 00100100 | 3     | 3      | Jump to the address in the next two bytes if `overflow` is set               |
+//! | jmpno   | 00100101 | 3     | 3      | Jump to the address in the next two bytes if `overflow` is not set           |
+//! | jmpp    | 00100110 | 3     | 3      | Jump to the address in the next two bytes if `parity` is set                 |
+//! | jmpnp   | 00100111 | 3     | 3      | Jump to the address in the next two bytes if `parity` is not set             |
+//! | cmp     | 00101000 | 1     | 1      | Subtract `b` from `a`, updating the ALU outputs without storing the result   |
+//! | pusha   | 00101001 | 1     | 3      | Push the `a` register onto the stack                                         |
+//! | pushb   | 00101010 | 1     | 3      | Push the `b` register onto the stack                                         |
+//! | pushf   | 00101011 | 1     | 3      | Push the flags of the ALU outputs onto the stack                             |
+//! | popa    | 00101100 | 1     | 3      | Pop the top of the stack into the `a` register                               |
+//! | popb    | 00101101 | 1     | 3      | Pop the top of the stack into the `b` register                               |
+//! | popf    | 00101110 | 1     | 3      | Pop the top of the stack into the flags of the ALU outputs                   |
+//! | call    | 00101111 | 3     | 5      | Call the subroutine at the address in the next two bytes                     |
+//! | ret     | 00110000 | 1     | 3      | Pop an address from the stack and jump to it                                 |
+//! | ei      | 00110001 | 1     | 1      | Enable interrupts                                                            |
+//! | di      | 00110010 | 1     | 1      | Disable interrupts                                                           |
+//! | reti    | 00110011 | 1     | 5      | Return from an interrupt handler, enabling interrupts again                  |
+//! | setim   | 00110100 | 2     | 2      | Set the interrupt mask to the next byte                                      |
+//! | adc     | 00110101 | 1     | 1      | Add `b` and `carry_out` to `a`, storing the result in `a`                    |
+//! | sbc     | 00110110 | 1     | 1      | Subtract `b` and `carry_out` from `a`, storing the result in `a`             |
+//! | rotlc   | 00110111 | 1     | 1      | Rotate `a` left by `b` bits through `carry_out`                              |
+//! | rotrc   | 00111000 | 1     | 1      | Rotate `a` right by `b` bits through `carry_out`                             |
+//! | mul     | 00111001 | 1     | 4      | Unsigned multiply `a` by `b`, storing the low word in `a` and high in `b`    |
+//! | imul    | 00111010 | 1     | 4      | Signed multiply `a` by `b`, storing the low word in `a` and high in `b`      |
+//! | div     | 00111011 | 1     | 8      | Unsigned divide `a` by `b`, storing the quotient in `a`                      |
+//! | idiv    | 00111100 | 1     | 8      | Signed divide `a` by `b`, storing the quotient in `a`                        |
+//! | mod     | 00111101 | 1     | 8      | Unsigned divide `a` by `b`, storing the remainder in `a`                     |
+//! | imod    | 00111110 | 1     | 8      | Signed divide `a` by `b`, storing the remainder in `a`                       |
+//! | ldai    | 00111111 | 1     | 3      | Load the word at the address in `b` to the `a` register                      |
+//! | stai    | 01000000 | 1     | 3      | Store the value in the `a` register to the address in `b`                    |
+//! | ldax    | 01000001 | 3     | 5      | Load the word at the address in the next two bytes plus `b` to `a`           |
+//! | stax    | 01000010 | 3     | 5      | Store the value in `a` to the address in the next two bytes plus `b`         |
+//! | ldaip   | 01000011 | 1     | 3      | Load the word at the address in `b` to `a`, then add `2` to `b`              |
+//! | staip   | 01000100 | 1     | 3      | Store the value in `a` to the address in `b`, then add `2` to `b`            |
+//! | mov     | 01000101 | 2     | 2      | Copy the second register in the next byte to the first                       |
+//! | swap    | 01000110 | 2     | 2      | Exchange the values of the two registers in the next byte                    |
+//! | halt    | 01000111 | 1     | 1      | Stop the virtual machine with the exit code in the `a` register              |
+//! | wait    | 01001000 | 1     | 1      | Idle until an unmasked interrupt is raised                                   |
 //!
 //! > Note: In the table, the `Value` column represents the first byte of an
-//! instruction being executed. The `Bytes` column displays how many bytes this
-//! instruction will take, including the opcode's byte.
+//! > instruction being executed. The `Bytes` column displays how many bytes this
+//! > instruction will take, including the opcode's byte, as reported by
+//! > [`Opcode::size`]. The `Cycles` column displays how many machine cycles
+//! > this instruction takes, as reported by [`Opcode::cycles`].
+//!
+//! Two-byte operands and the words accessed by the load and store
+//! instructions are little-endian. Instruction fetches, loads, stores, the
+//! stack and the vector table all share a single 16-bit address space, into
+//! which ROM, RAM and devices are mapped by the virtual machine.
+//!
+//! The stack lives at the end of RAM and grows downward. The stack pointer
+//! holds the address of the most recently pushed value and starts just past
+//! the end of RAM, wrapping around to `0` at the end of the address space,
+//! while the stack is empty. Every push and pop moves a little-endian word,
+//! with the flags packed as described by [`AluOutputs::flags`].
+//!
+//! The conditional jumps test the flags of the last ALU outputs, so a `cmp`
+//! followed by `jmpz` jumps when `a` and `b` are equal, and a `cmp` followed
+//! by `jmpc` jumps when `a` is less than `b` as unsigned numbers.
+//!
+//! ### Registers
+//!
+//! Besides the program counter and stack pointer, the virtual machine has
+//! the general-purpose registers `a`, `b`, `c` and `d`, and a flags register
+//! holding the flags of the last ALU outputs packed as described by
+//! [`AluOutputs::flags`]. The ALU and memory instructions work on `a` and
+//! `b`, while `c` and `d` hold values aside until `mov` or `swap` brings them
+//! back.
+//!
+//! `mov` and `swap` take a pair of registers in their operand byte, the
+//! number of the first in the high four bits and the second in the low four
+//! bits, numbered as in [`Register`]. `mov` copies the second register into
+//! the first. Neither changes the ALU outputs, apart from replacing their
+//! flags when the flags register is written, so `mov` can save the flags to
+//! a general-purpose register and restore them later. A register number that
+//! isn't in [`Register`] makes the instruction illegal.
+//!
+//! ### Addressing modes
+//!
+//! The loads and stores form the address of the word they access in one of
+//! the ways described by [`AddressMode`]:
+//!
+//! - `sta16` and `stb16` store to the address in their operand.
+//! - `sta8` and `stb8` store to the zero page, the first 256 bytes of RAM,
+//!   at the offset in their operand. The zero page follows RAM wherever it is
+//!   mapped, so these stores always reach RAM.
+//! - `ldai` and `stai` access the address held in `b`, which makes `b` a
+//!   pointer.
+//! - `ldax` and `stax` access the address in their operand plus `b`, so the
+//!   operand can be the start of an array or structure and `b` the offset of
+//!   an element within it.
+//! - `ldaip` and `staip` access the address held in `b` and then add `2` to
+//!   `b`, moving it on to the next word, for walking through arrays and
+//!   strings stored a word at a time.
+//!
+//! None of them change the ALU outputs. A load or store that fails leaves `b`
+//! as it was.
+//!
+//! ### Multi-precision arithmetic
+//!
+//! Numbers wider than a word are handled a word at a time, starting with the
+//! least significant word, by passing `carry_out` from one word to the next.
+//! The loads and stores leave the ALU outputs alone, so the carry survives
+//! between the words. Adding the 32-bit number `0x1234_5678` to `0x9ABC_DEF0`
+//! looks like this:
+//!
+//! ```text
+//! lda16 0x5678  ; the low words
+//! ldb16 0xDEF0
+//! add           ; carry_out is the carry out of the low words
+//! sta16 low
+//! lda16 0x1234  ; the high words
+//! ldb16 0x9ABC
+//! adc           ; add the high words and the carry
+//! sta16 high
+//! ```
+//!
+//! Subtraction works the same way with `sub` and then `sbc`, where
+//! `carry_out` is the borrow. To shift a wide number left by one bit, shift
+//! the lowest word with `ushftl` and then each word above it with `rotlc`,
+//! which moves the bit shifted out of the word below into the lowest bit.
+//! Shifting right starts at the highest word with `ushftr` (or `shftr` to
+//! keep the sign) followed by `rotrc` on each word below it, with `b` set to
+//! `1` throughout.
+//!
+//! ### Multiplication and division
+//!
+//! `mul` and `imul` multiply `a` by `b` into a 32-bit product, with the low
+//! word going to `a` and the high word to `b`. The ALU outputs describe the
+//! low word, except that `carry_out` and `overflow` are both set when the
+//! high word is significant: for `mul` when it isn't `0`, and for `imul` when
+//! the product doesn't fit in a signed word, so that the high word isn't just
+//! the sign of the low word repeated.
+//!
+//! `div` and `idiv` store the quotient in `a`, and `mod` and `imod` the
+//! remainder, leaving `b` alone. Signed division rounds toward zero, so the
+//! remainder takes the sign of `a`. `carry_out` is always cleared, and
+//! `overflow` is only set by `idiv` when dividing `-32768` by `-1`, whose
+//! quotient wraps around to `-32768`. The remainder of that division is `0`.
+//!
+//! Dividing by zero is an exception: the instruction doesn't complete, and
+//! the virtual machine stops with the program counter left on it.
+//!
+//! ### Timing
+//!
+//! Time on the virtual machine is measured in machine cycles. An instruction
+//! takes one cycle for each of its bytes, plus one for each byte it reads or
+//! writes in memory, so a `call` takes three cycles to fetch and two more to
+//! push the return address. Multiplication takes three extra cycles and
+//! division seven. Taking an interrupt takes [`INTERRUPT_CYCLES`].
+//! Devices count the same cycles, which keeps programs deterministic
+//! regardless of how fast the host runs them.
+//!
+//! ### Interrupts
+//!
+//! There are [`IRQ_LINES`] numbered interrupt lines, where lower numbered
+//! lines take priority over higher numbered ones. A line is masked while its
+//! bit in the interrupt mask is set. Interrupts start disabled and are only
+//! taken between instructions while they are enabled.
+//!
+//! Taking an interrupt pushes the address of the next instruction and then
+//! the flags onto the stack, disables interrupts and jumps to the handler
+//! address for the line. The handler addresses are little-endian words in the
+//! vector table starting at [`VECTOR_TABLE`], one for each line in order. A
+//! handler returns with `reti`.
+//!
+//! ### Halting and waiting
+//!
+//! A program finishes with `halt`, which stops the virtual machine for good
+//! with the value of `a` as its exit code. `wait` idles the virtual machine,
+//! one cycle at a time so that devices keep running, until an interrupt is
+//! raised on a line that isn't masked. The interrupt is then taken if
+//! interrupts are enabled, and otherwise execution carries on after the
+//! `wait` with the line that woke it cleared, as if its interrupt had been
+//! taken, so that the next `wait` waits for another one. Any other lines
+//! raised at the same time stay pending, so the next `wait` carries straight
+//! on. [`VirtualMachine::state`] tells which of these a virtual machine is
+//! in, as a [`MachineState`].
+//!
+//! ### ROM images
+//!
+//! Programs are distributed as ROM images, laid out as follows with every
+//! field little-endian:
+//!
+//! | Offset | Bytes | Field                                                   |
+//! |--------|-------|---------------------------------------------------------|
+//! | 0      | 4     | The magic number [`IMAGE_MAGIC`]                        |
+//! | 4      | 2     | The version of this format, [`IMAGE_VERSION`]           |
+//! | 6      | 2     | The entry point, where execution starts                 |
+//! | 8      | 2     | The load address, which segment offsets are relative to |
+//! | 10     | 2     | The number of segments                                  |
+//! | 12     |       | The segments, one after another                         |
+//! |        | 4     | The CRC-32 checksum of every byte before it             |
+//!
+//! Each segment is a 2-byte offset and a 4-byte length followed by that many
+//! bytes of data, which are placed in ROM at the load address plus the
+//! offset. Segments may not overlap or extend past the end of the address
+//! space, and ROM addresses not covered by a segment are filled with `0`.
+//! The checksum is the common CRC-32 used by zip and PNG files.
+
+mod device;
+mod instruction;
+mod register;
+
+pub use device::*;
+pub use instruction::*;
+pub use register::*;
+
+use std::convert::TryFrom;
 
 type Ty = u16;
 
+/// The number of addresses in the 16-bit address space, which is also the
+/// largest size of a memory container.
+pub const ADDRESS_SPACE: u32 = 1 << 16;
+
+/// The number of interrupt lines.
+pub const IRQ_LINES: u8 = 8;
+
+/// The address of the interrupt vector table, which fills the end of the
+/// first 32 KiB of the address space.
+pub const VECTOR_TABLE: u16 = 0x7FF0;
+
+/// The number of cycles taken to enter an interrupt handler, which pushes two
+/// words and reads the handler address from the vector table.
+pub const INTERRUPT_CYCLES: u32 = 6;
+
+/// The magic number at the start of every ROM image.
+pub const IMAGE_MAGIC: [u8; 4] = *b"CJEM";
+
+/// The version of the ROM image format described by this specification.
+pub const IMAGE_VERSION: u16 = 1;
+
 /// The possible operations that can be performed by the emulator (on the cycle level).
 #[repr(u8)]
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -49,7 +301,7 @@ pub enum Opcode {
 
     /// Add the values in the `A` and `B` registers.
     Add,
-    /// Add the values in the `A` and `B` registers.
+    /// Subtract the value in the `B` register from the `A` register.
     Sub,
     /// Negate the value in the `A` register.
     NegA,
@@ -88,6 +340,315 @@ pub enum Opcode {
     RotL,
     /// Rotate the bits in `A` right by the value of `B`
     RotR,
+
+    /// Jump to the address in the next two bytes.
+    Jmp,
+    /// Jump by the signed offset in the next byte, relative to the address of
+    /// the following instruction.
+    JmpRel,
+    /// Jump to the address in the next two bytes if the `zero` flag is set.
+    JmpZ,
+    /// Jump to the address in the next two bytes if the `zero` flag is not set.
+    JmpNZ,
+    /// Jump to the address in the next two bytes if the `carry_out` flag is set.
+    JmpC,
+    /// Jump to the address in the next two bytes if the `carry_out` flag is not
+    /// set.
+    JmpNC,
+    /// Jump to the address in the next two bytes if the `negative` flag is set.
+    JmpN,
+    /// Jump to the address in the next two bytes if the `negative` flag is not
+    /// set.
+    JmpNN,
+    /// Jump to the address in the next two bytes if the `overflow` flag is set.
+    JmpO,
+    /// Jump to the address in the next two bytes if the `overflow` flag is not
+    /// set.
+    JmpNO,
+    /// Jump to the address in the next two bytes if the `parity` flag is set.
+    JmpP,
+    /// Jump to the address in the next two bytes if the `parity` flag is not
+    /// set.
+    JmpNP,
+
+    /// Subtract the value in the `B` register from the `A` register, updating
+    /// the ALU outputs without storing the result.
+    Cmp,
+
+    /// Push the value in the `A` register onto the stack.
+    PushA,
+    /// Push the value in the `B` register onto the stack.
+    PushB,
+    /// Push the flags of the ALU outputs onto the stack.
+    PushF,
+    /// Pop the value on top of the stack into the `A` register.
+    PopA,
+    /// Pop the value on top of the stack into the `B` register.
+    PopB,
+    /// Pop the value on top of the stack into the flags of the ALU outputs.
+    PopF,
+    /// Push the address of the next instruction onto the stack and jump to the
+    /// address in the next two bytes.
+    Call,
+    /// Pop an address off of the stack and jump to it.
+    Ret,
+
+    /// Enable interrupts.
+    Ei,
+    /// Disable interrupts.
+    Di,
+    /// Pop the flags and then an address off of the stack, jump to the
+    /// address and enable interrupts.
+    RetI,
+    /// Set the interrupt mask to the next byte.
+    SetIM,
+
+    /// Add the value in the `B` register and the `carry_out` flag to the `A`
+    /// register.
+    Adc,
+    /// Subtract the value in the `B` register and the `carry_out` flag, as a
+    /// borrow, from the `A` register.
+    Sbc,
+    /// Rotate the bits of the `A` register left by the value in the `B`
+    /// register through the `carry_out` flag.
+    RotLC,
+    /// Rotate the bits of the `A` register right by the value in the `B`
+    /// register through the `carry_out` flag.
+    RotRC,
+
+    /// Multiply the `A` register by the `B` register as unsigned numbers,
+    /// storing the low word of the product in `A` and the high word in `B`.
+    Mul,
+    /// Multiply the `A` register by the `B` register as signed numbers,
+    /// storing the low word of the product in `A` and the high word in `B`.
+    IMul,
+    /// Divide the `A` register by the `B` register as unsigned numbers,
+    /// storing the quotient in `A`.
+    Div,
+    /// Divide the `A` register by the `B` register as signed numbers, storing
+    /// the quotient in `A`.
+    IDiv,
+    /// Divide the `A` register by the `B` register as unsigned numbers,
+    /// storing the remainder in `A`.
+    Mod,
+    /// Divide the `A` register by the `B` register as signed numbers, storing
+    /// the remainder in `A`.
+    IMod,
+
+    /// Load the word at the address in the `B` register into the `A`
+    /// register.
+    LdAI,
+    /// Store the value in the `A` register to the address in the `B`
+    /// register.
+    StAI,
+    /// Load the word at the address in the next two bytes plus the `B`
+    /// register into the `A` register.
+    LdAX,
+    /// Store the value in the `A` register to the address in the next two
+    /// bytes plus the `B` register.
+    StAX,
+    /// Load the word at the address in the `B` register into the `A` register
+    /// and then add `2` to the `B` register.
+    LdAIP,
+    /// Store the value in the `A` register to the address in the `B` register
+    /// and then add `2` to the `B` register.
+    StAIP,
+
+    /// Copy the value of the second register in the next byte to the first.
+    Mov,
+    /// Exchange the values of the two registers in the next byte.
+    Swap,
+
+    /// Stop the virtual machine, with the value in the `A` register as its
+    /// exit code.
+    Halt,
+    /// Idle until an unmasked interrupt is raised.
+    Wait,
+}
+
+impl Opcode {
+    /// Every opcode, indexed by the value of its byte.
+    pub const ALL: [Opcode; 73] = [
+        Self::NoOp,
+        Self::LdA16,
+        Self::LdB16,
+        Self::StA16,
+        Self::StB16,
+        Self::LdA8,
+        Self::LdB8,
+        Self::StA8,
+        Self::StB8,
+        Self::Add,
+        Self::Sub,
+        Self::NegA,
+        Self::NegB,
+        Self::IncA,
+        Self::IncB,
+        Self::PassA,
+        Self::PassB,
+        Self::And,
+        Self::Or,
+        Self::XOr,
+        Self::BitFlpA,
+        Self::BitFlpB,
+        Self::ShftL,
+        Self::ShftR,
+        Self::UShftL,
+        Self::UShftR,
+        Self::RotL,
+        Self::RotR,
+        Self::Jmp,
+        Self::JmpRel,
+        Self::JmpZ,
+        Self::JmpNZ,
+        Self::JmpC,
+        Self::JmpNC,
+        Self::JmpN,
+        Self::JmpNN,
+        Self::JmpO,
+        Self::JmpNO,
+        Self::JmpP,
+        Self::JmpNP,
+        Self::Cmp,
+        Self::PushA,
+        Self::PushB,
+        Self::PushF,
+        Self::PopA,
+        Self::PopB,
+        Self::PopF,
+        Self::Call,
+        Self::Ret,
+        Self::Ei,
+        Self::Di,
+        Self::RetI,
+        Self::SetIM,
+        Self::Adc,
+        Self::Sbc,
+        Self::RotLC,
+        Self::RotRC,
+        Self::Mul,
+        Self::IMul,
+        Self::Div,
+        Self::IDiv,
+        Self::Mod,
+        Self::IMod,
+        Self::LdAI,
+        Self::StAI,
+        Self::LdAX,
+        Self::StAX,
+        Self::LdAIP,
+        Self::StAIP,
+        Self::Mov,
+        Self::Swap,
+        Self::Halt,
+        Self::Wait,
+    ];
+
+    /// The kind of operand that follows this opcode.
+    pub fn operand(self) -> OperandKind {
+        match self {
+            Self::LdA16 | Self::LdB16 => OperandKind::Imm16,
+            Self::StA16 | Self::StB16 | Self::LdAX | Self::StAX => OperandKind::Addr16,
+            Self::LdA8 | Self::LdB8 | Self::SetIM => OperandKind::Imm8,
+            Self::StA8 | Self::StB8 => OperandKind::Addr8,
+            Self::Jmp
+            | Self::Call
+            | Self::JmpZ
+            | Self::JmpNZ
+            | Self::JmpC
+            | Self::JmpNC
+            | Self::JmpN
+            | Self::JmpNN
+            | Self::JmpO
+            | Self::JmpNO
+            | Self::JmpP
+            | Self::JmpNP => OperandKind::Addr16,
+            Self::JmpRel => OperandKind::Rel8,
+            Self::Mov | Self::Swap => OperandKind::Registers,
+            _ => OperandKind::None,
+        }
+    }
+
+    /// How an instruction with this opcode forms the address it loads from or
+    /// stores to.
+    pub fn address_mode(self) -> AddressMode {
+        match self {
+            Self::StA16 | Self::StB16 => AddressMode::Direct,
+            Self::StA8 | Self::StB8 => AddressMode::ZeroPage,
+            Self::LdAI | Self::StAI => AddressMode::Indirect,
+            Self::LdAX | Self::StAX => AddressMode::Indexed,
+            Self::LdAIP | Self::StAIP => AddressMode::PostIncrement,
+            _ => AddressMode::None,
+        }
+    }
+
+    /// The number of bytes an instruction with this opcode takes, including
+    /// the opcode's byte.
+    pub fn size(self) -> u16 {
+        1 + self.operand().size()
+    }
+
+    /// The number of machine cycles an instruction with this opcode takes.
+    pub fn cycles(self) -> u32 {
+        // One cycle for each byte read or written in memory on top of the
+        // bytes of the instruction itself
+        let memory = match self {
+            Self::StA16
+            | Self::StB16
+            | Self::StA8
+            | Self::StB8
+            | Self::LdAI
+            | Self::StAI
+            | Self::LdAX
+            | Self::StAX
+            | Self::LdAIP
+            | Self::StAIP
+            | Self::PushA
+            | Self::PushB
+            | Self::PushF
+            | Self::PopA
+            | Self::PopB
+            | Self::PopF
+            | Self::Call
+            | Self::Ret => 2,
+            Self::RetI => 4,
+            _ => 0,
+        };
+        // The ALU takes longer to multiply and longer still to divide
+        let alu = match self {
+            Self::Mul | Self::IMul => 3,
+            Self::Div | Self::IDiv | Self::Mod | Self::IMod => 7,
+            _ => 0,
+        };
+        self.size() as u32 + memory + alu
+    }
+}
+
+impl TryFrom<u8> for Opcode {
+    type Error = DecodeError;
+
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        Self::ALL
+            .get(byte as usize)
+            .copied()
+            .ok_or(DecodeError::IllegalOpcode(byte))
+    }
+}
+
+/// Whether a virtual machine is executing instructions.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MachineState {
+    /// The virtual machine is executing instructions.
+    Running,
+    /// The virtual machine is idling until an unmasked interrupt is raised.
+    Waiting,
+    /// A `halt` instruction stopped the virtual machine with this exit code.
+    Halted {
+        /// The value of the `a` register when the virtual machine halted.
+        exit_code: u16,
+    },
+    /// An error stopped the virtual machine.
+    Faulted,
 }
 
 /// Represents a container for the virtual machine's data.
@@ -98,10 +659,18 @@ pub trait VirtualMachine<Rom: ReadableMemory, Ram: ReadableMemory> {
     /// Retrieve the last state of the ALU outputs.
     fn last_alu(&self) -> AluOutputs;
 
-    /// Retrieve the value of the `a` register.
-    fn reg_a(&self) -> u16;
-    /// Retrieve the value of the `a` register.
-    fn reg_b(&self) -> u16;
+    /// Retrieve whether the virtual machine is running, waiting or stopped.
+    fn state(&self) -> MachineState;
+
+    /// Retrieve the address of the next instruction to be executed.
+    fn pc(&self) -> u16;
+
+    /// Retrieve the address of the value on top of the stack.
+    fn sp(&self) -> u16;
+
+    /// Retrieve the value of a general-purpose register or the flags
+    /// register.
+    fn register(&self, register: Register) -> u16;
 
     /// The read-only memory available to the virtual machine.
     fn rom(&self) -> &Rom;
@@ -109,14 +678,63 @@ pub trait VirtualMachine<Rom: ReadableMemory, Ram: ReadableMemory> {
     /// The random access memory available to the virtual machine.
     fn ram(&self) -> &Ram;
 
-    /// Attempts to perform a clock cycle on this virtual machine
+    /// The number of machine cycles that have passed since the virtual
+    /// machine started.
+    fn cycles(&self) -> u64;
+
+    /// The number of instructions that have been executed since the virtual
+    /// machine started.
+    fn instructions_retired(&self) -> u64;
+
+    /// Raise the interrupt `line`, or return `None` if there is no such line.
+    /// The interrupt stays pending until it is taken.
+    fn raise_interrupt(&mut self, line: u8) -> Option<()>;
+
+    /// Attempts to execute an instruction, or enter an interrupt handler,
+    /// on this virtual machine, taking one or more machine cycles.
     fn perform_tick(&mut self) -> Result<(), Self::TickErrorTy>;
 }
 
+/// Debugger access to a virtual machine, for front ends and tools that edit
+/// its state rather than only inspect it. Programs running on the virtual
+/// machine can't do any of this.
+pub trait DebugVirtualMachine<Rom: ReadableMemory, Ram: WritableMemory>:
+    VirtualMachine<Rom, Ram>
+{
+    /// Set the value of a general-purpose register, or replace the flags of
+    /// the ALU outputs through the flags register.
+    fn set_register(&mut self, register: Register, value: u16);
+
+    /// Set the address of the next instruction to be executed.
+    fn set_pc(&mut self, pc: u16);
+
+    /// The random access memory available to the virtual machine, for
+    /// editing.
+    fn ram_mut(&mut self) -> &mut Ram;
+
+    /// Overwrite the byte of read-only memory at `address`, or return `None`
+    /// if the address is out of its bounds.
+    ///
+    /// This is a debug operation for patching programs while they run, since
+    /// ROM can never be written by the virtual machine itself.
+    fn patch_rom(&mut self, address: u16, value: u8) -> Option<()>;
+
+    /// Put the virtual machine back into the state it started in, clearing
+    /// the registers, flags, stack, interrupts and counters, running again
+    /// if it halted, waited or faulted, and moving the program counter to the
+    /// entry point. The contents of memory are left alone.
+    ///
+    /// Devices attached to the virtual machine are put back into the state
+    /// they started in too, so that they don't carry on counting or raising
+    /// interrupts from before the reset.
+    fn reset(&mut self);
+}
+
 /// Represents a read-only memory container.
 pub trait ReadableMemory {
-    /// The number of bytes this memory container may hold.
-    fn size(&self) -> u16;
+    /// The number of bytes this memory container may hold, at most
+    /// [`ADDRESS_SPACE`].
+    fn size(&self) -> u32;
 
     /// Retrieves the byte at this address, or `None` if the address is out of
     /// this memory's bounds.
@@ -131,30 +749,75 @@ pub trait WritableMemory: ReadableMemory {
 }
 
 /// A wrapper around the outputs of an ALU after performing an operation.
-#[derive(Copy, Clone, Debug, Default)]
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
 pub struct AluOutputs {
     /// The value of the previous operation.
     pub value: Ty,
 
     /// Whether the previous addition operation resulted in a final carry, the
-    /// previous subtraction operation resulted in a final borrow, or there was
-    /// a bitshifting overflow.
+    /// previous subtraction operation resulted in a final borrow, or the last
+    /// bit shifted or rotated out of the value was a `1`. Always `false` for
+    /// the bit logic operations.
     pub carry_out: bool,
 
     /// Whether the previous arithmetic operation is equivalent to 0.
     pub zero: bool,
 
-    /// Whether the previous arithmetic operation resulted in a negative number.
+    /// Whether the previous arithmetic operation resulted in a negative number,
+    /// meaning the most significant bit of the value is set.
     pub negative: bool,
 
     /// Whether the previous operation resulted in a value larger than can be
-    /// held in the number of bytes available.
+    /// held in the number of bytes available, when the inputs and output are
+    /// treated as two's complement numbers. Always `false` for the bit logic,
+    /// logical shift, right shift and rotate operations.
     pub overflow: bool,
 
-    /// Whether there are an even (or odd) number of `1` bits in the previous output.
+    /// Whether there are an even number of `1` bits in the previous output.
     pub parity: bool,
 }
 
+impl AluOutputs {
+    /// The bit of the packed flags holding `carry_out`.
+    pub const CARRY_OUT: u16 = 1 << 0;
+    /// The bit of the packed flags holding `zero`.
+    pub const ZERO: u16 = 1 << 1;
+    /// The bit of the packed flags holding `negative`.
+    pub const NEGATIVE: u16 = 1 << 2;
+    /// The bit of the packed flags holding `overflow`.
+    pub const OVERFLOW: u16 = 1 << 3;
+    /// The bit of the packed flags holding `parity`.
+    pub const PARITY: u16 = 1 << 4;
+
+    /// Pack the flags of these outputs into the bits of a word, leaving the
+    /// unused bits clear.
+    pub fn flags(&self) -> u16 {
+        let mut flags = 0;
+        for &(flag, bit) in &[
+            (self.carry_out, Self::CARRY_OUT),
+            (self.zero, Self::ZERO),
+            (self.negative, Self::NEGATIVE),
+            (self.overflow, Self::OVERFLOW),
+            (self.parity, Self::PARITY),
+        ] {
+            if flag {
+                flags |= bit;
+            }
+        }
+        flags
+    }
+
+    /// Replace the flags of these outputs with those packed into `flags` by
+    /// [`AluOutputs::flags`], leaving the value untouched.
+    pub fn set_flags(&mut self, flags: u16) {
+        self.carry_out = flags & Self::CARRY_OUT != 0;
+        self.zero = flags & Self::ZERO != 0;
+        self.negative = flags & Self::NEGATIVE != 0;
+        self.overflow = flags & Self::OVERFLOW != 0;
+        self.parity = flags & Self::PARITY != 0;
+    }
+}
+
 /// Represents an arithmetic logic unit.
 pub trait Alu {
     // Arithmetic
@@ -195,22 +858,50 @@ pub trait Alu {
     /// outputs. The `a` value is treated as a two's complement, so the most
     /// significant bit is preserved.
     fn shift16l(&mut self, a: Ty, b: Ty) -> AluOutputs;
-    /// Perform an arithmetic bitshift left by `b` bits on `a` and return the
-    /// outputs.The `a` value is treated as a two's complement, so the most
-    /// significant bit is preserved.
+    /// Perform an arithmetic bitshift right by `b` bits on `a` and return the
+    /// outputs. The `a` value is treated as a two's complement, so the most
+    /// significant bit is copied into the vacated bits.
     fn shift16r(&mut self, a: Ty, b: Ty) -> AluOutputs;
     /// Perform a logical bitshift left by `b` bits on `a` and return the
     /// outputs. The `a` value is treated as an unsigned integer.
     fn ushift16l(&mut self, a: Ty, b: Ty) -> AluOutputs;
-    /// Perform a logical bitshift left by `b` bits on `a` and return the
+    /// Perform a logical bitshift right by `b` bits on `a` and return the
     /// outputs. The `a` value is treated as an unsigned integer.
     fn ushift16r(&mut self, a: Ty, b: Ty) -> AluOutputs;
     /// Rotate the bits left `b` times in `a`.
     fn rot16l(&mut self, a: Ty, b: Ty) -> AluOutputs;
     /// Rotate the bits right `b` times in `a`.
     fn rot16r(&mut self, a: Ty, b: Ty) -> AluOutputs;
-    /// Rotate the bits left `b` times in `a` with the carry bit.
+    /// Rotate the bits left `b` times in `a` with the carry bit, as if the
+    /// carry were a 17th bit above the most significant bit of `a`.
     fn rot16l_carry(&mut self, a: Ty, b: Ty, carry: bool) -> AluOutputs;
-    /// Rotate the bits right `b` times in `a` with the carry bit.
+    /// Rotate the bits right `b` times in `a` with the carry bit, as if the
+    /// carry were a 17th bit above the most significant bit of `a`.
     fn rot16r_carry(&mut self, a: Ty, b: Ty, carry: bool) -> AluOutputs;
+
+    // Multiplication and division
+
+    /// Multiply `a` by `b` as unsigned numbers, returning the outputs for the
+    /// low word of the product and the high word. `carry_out` and `overflow`
+    /// are set when the high word isn't `0`.
+    fn mul16(&mut self, a: Ty, b: Ty) -> (AluOutputs, Ty);
+    /// Multiply `a` by `b` as signed numbers, returning the outputs for the
+    /// low word of the product and the high word. `carry_out` and `overflow`
+    /// are set when the product doesn't fit in the low word.
+    fn imul16(&mut self, a: Ty, b: Ty) -> (AluOutputs, Ty);
+    /// Divide `a` by `b` as unsigned numbers and return the outputs for the
+    /// quotient, or `None` if `b` is `0`.
+    fn div16(&mut self, a: Ty, b: Ty) -> Option<AluOutputs>;
+    /// Divide `a` by `b` as signed numbers, rounding toward zero, and return
+    /// the outputs for the quotient, or `None` if `b` is `0`. `overflow` is
+    /// set when the quotient wraps around, which only happens when dividing
+    /// the most negative value by `-1`.
+    fn idiv16(&mut self, a: Ty, b: Ty) -> Option<AluOutputs>;
+    /// Divide `a` by `b` as unsigned numbers and return the outputs for the
+    /// remainder, or `None` if `b` is `0`.
+    fn mod16(&mut self, a: Ty, b: Ty) -> Option<AluOutputs>;
+    /// Divide `a` by `b` as signed numbers, rounding toward zero, and return
+    /// the outputs for the remainder, which takes the sign of `a`, or `None`
+    /// if `b` is `0`.
+    fn imod16(&mut self, a: Ty, b: Ty) -> Option<AluOutputs>;
 }
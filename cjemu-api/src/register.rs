@@ -0,0 +1,38 @@
+use crate::DecodeError;
+use std::convert::TryFrom;
+
+/// The registers that instructions can move values between, numbered by how
+/// they are encoded in a register operand.
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Register {
+    /// The `a` general-purpose register, which most instructions work on.
+    A,
+    /// The `b` general-purpose register, which holds the second input of the
+    /// ALU and the pointer or index used by the addressing modes.
+    B,
+    /// The `c` general-purpose register.
+    C,
+    /// The `d` general-purpose register.
+    D,
+    /// The flags register, holding the flags of the last ALU outputs packed as
+    /// described by [`AluOutputs::flags`](crate::AluOutputs::flags). Writing to
+    /// it replaces those flags.
+    Flags,
+}
+
+impl Register {
+    /// Every register, indexed by its number.
+    pub const ALL: [Register; 5] = [Self::A, Self::B, Self::C, Self::D, Self::Flags];
+}
+
+impl TryFrom<u8> for Register {
+    type Error = DecodeError;
+
+    fn try_from(number: u8) -> Result<Self, Self::Error> {
+        Self::ALL
+            .get(number as usize)
+            .copied()
+            .ok_or(DecodeError::IllegalRegister(number))
+    }
+}
@@ -0,0 +1,116 @@
+//! Checks that instructions decode back into what they were encoded from, and
+//! that malformed instructions fail to decode.
+
+use cjemu_api::{DecodeError, Instruction, Opcode, Operand, OperandKind, ReadableMemory, Register};
+use std::convert::TryFrom;
+
+// A memory holding just the bytes of the instructions under test
+struct Bytes(Vec<u8>);
+
+impl ReadableMemory for Bytes {
+    fn size(&self) -> u32 {
+        self.0.len() as u32
+    }
+
+    fn byte(&self, address: u16) -> Option<u8> {
+        self.0.get(address as usize).copied()
+    }
+}
+
+// Operands of `kind`, including the extremes of their values
+fn operands(kind: OperandKind) -> Vec<Operand> {
+    let bytes = [0, 1, 0x7F, 0x80, 0xFF];
+    let words = [0, 1, 0x00FF, 0x7FFF, 0x8000, 0xFFFF];
+    match kind {
+        OperandKind::None => vec![Operand::None],
+        OperandKind::Imm8 => bytes.iter().map(|&b| Operand::Imm8(b)).collect(),
+        OperandKind::Imm16 => words.iter().map(|&w| Operand::Imm16(w)).collect(),
+        OperandKind::Addr8 => bytes.iter().map(|&b| Operand::Addr8(b)).collect(),
+        OperandKind::Addr16 => words.iter().map(|&w| Operand::Addr16(w)).collect(),
+        OperandKind::Rel8 => bytes.iter().map(|&b| Operand::Rel8(b as i8)).collect(),
+        OperandKind::Registers => Register::ALL
+            .iter()
+            .flat_map(|&first| {
+                Register::ALL
+                    .iter()
+                    .map(move |&second| Operand::Registers(first, second))
+            })
+            .collect(),
+    }
+}
+
+#[test]
+fn round_trip() {
+    for &opcode in Opcode::ALL.iter() {
+        for operand in operands(opcode.operand()) {
+            let instruction = Instruction::new(opcode, operand).unwrap();
+            let bytes = instruction.encode();
+            assert_eq!(bytes.len(), opcode.size() as usize, "{:?}", instruction);
+            assert_eq!(
+                Instruction::decode(&Bytes(bytes), 0),
+                Ok(instruction),
+                "{:?}",
+                instruction
+            );
+        }
+    }
+}
+
+#[test]
+fn opcode_values() {
+    for (value, &opcode) in Opcode::ALL.iter().enumerate() {
+        assert_eq!(Opcode::try_from(value as u8), Ok(opcode));
+    }
+    for value in Opcode::ALL.len() as u8..=u8::MAX {
+        assert_eq!(
+            Opcode::try_from(value),
+            Err(DecodeError::IllegalOpcode(value))
+        );
+        assert_eq!(
+            Instruction::decode(&Bytes(vec![value, 0, 0]), 0),
+            Err(DecodeError::IllegalOpcode(value))
+        );
+    }
+}
+
+#[test]
+fn illegal_register() {
+    let illegal = Register::ALL.len() as u8;
+    for &opcode in &[Opcode::Mov, Opcode::Swap] {
+        for register in illegal..=0xF {
+            let first = Bytes(vec![opcode as u8, register << 4]);
+            assert_eq!(
+                Instruction::decode(&first, 0),
+                Err(DecodeError::IllegalRegister(register))
+            );
+            let second = Bytes(vec![opcode as u8, register]);
+            assert_eq!(
+                Instruction::decode(&second, 0),
+                Err(DecodeError::IllegalRegister(register))
+            );
+        }
+    }
+}
+
+#[test]
+fn truncated_operand() {
+    for &opcode in Opcode::ALL.iter().filter(|opcode| opcode.size() > 1) {
+        let bytes = Instruction::new(opcode, operands(opcode.operand())[0])
+            .unwrap()
+            .encode();
+        // Every prefix of the instruction that still holds its opcode
+        for len in 1..bytes.len() {
+            assert_eq!(
+                Instruction::decode(&Bytes(bytes[..len].to_vec()), 0),
+                Err(DecodeError::OutOfBounds(len as u16)),
+                "{:?} cut to {} bytes",
+                opcode,
+                len
+            );
+        }
+    }
+    assert_eq!(
+        Instruction::decode(&Bytes(Vec::new()), 0),
+        Err(DecodeError::OutOfBounds(0))
+    );
+}
@@ -1,95 +1,200 @@
 use cjemu_api::{Alu, AluOutputs};
 
-pub struct CJEmuAlu {}
+// The most significant bit of a value, which holds its sign
+const SIGN_BIT: u16 = 1 << 15;
 
-#[allow(unused_variables)]
-impl Alu for CJEmuAlu {
-    fn add16(&mut self, a: u16, b: u16) -> AluOutputs {
-        let (value, overflow) = match a.checked_add(b) {
-            Some(val) => (val, false),
-            // Wrap back around in the event of an overflow
-            None => (b - (u16::MAX - a), true),
-        };
+pub struct CJEmuAlu {}
 
+impl CJEmuAlu {
+    // Wrap a result into ALU outputs, deriving the flags that only depend on
+    // the value itself
+    fn outputs(value: u16, carry_out: bool, overflow: bool) -> AluOutputs {
         AluOutputs {
             value,
-            carry_out: false, // TODO
+            carry_out,
             zero: value == 0,
-            negative: false,
+            negative: value & SIGN_BIT != 0,
             overflow,
-            parity: false, // TODO
+            parity: value.count_ones() & 1 == 0,
         }
     }
+}
+
+impl Alu for CJEmuAlu {
+    fn add16(&mut self, a: u16, b: u16) -> AluOutputs {
+        self.add16_carry(a, b, false)
+    }
 
     fn add16_carry(&mut self, a: u16, b: u16, carry: bool) -> AluOutputs {
-        todo!()
+        let (partial, carry_a) = a.overflowing_add(b);
+        let (value, carry_b) = partial.overflowing_add(carry as u16);
+
+        // Signed overflow occurs when both inputs share a sign that differs
+        // from the sign of the result
+        let overflow = (a ^ value) & (b ^ value) & SIGN_BIT != 0;
+
+        Self::outputs(value, carry_a || carry_b, overflow)
     }
 
     fn sub16(&mut self, a: u16, b: u16) -> AluOutputs {
-        todo!()
+        self.sub16_borrow(a, b, false)
     }
 
     fn sub16_borrow(&mut self, a: u16, b: u16, borrow: bool) -> AluOutputs {
-        todo!()
+        let (partial, borrow_a) = a.overflowing_sub(b);
+        let (value, borrow_b) = partial.overflowing_sub(borrow as u16);
+
+        // Signed overflow occurs when the inputs have different signs and the
+        // sign of the result differs from the sign of `a`
+        let overflow = (a ^ b) & (a ^ value) & SIGN_BIT != 0;
+
+        Self::outputs(value, borrow_a || borrow_b, overflow)
     }
 
     fn neg16(&mut self, a: u16) -> AluOutputs {
-        todo!()
+        self.sub16(0, a)
     }
 
     fn inc16(&mut self, a: u16) -> AluOutputs {
-        todo!()
+        self.add16(a, 1)
     }
 
     fn pass16(&mut self, a: u16) -> AluOutputs {
-        todo!()
+        Self::outputs(a, false, false)
     }
 
     fn and16(&mut self, a: u16, b: u16) -> AluOutputs {
-        todo!()
+        Self::outputs(a & b, false, false)
     }
 
     fn or16(&mut self, a: u16, b: u16) -> AluOutputs {
-        todo!()
+        Self::outputs(a | b, false, false)
     }
 
     fn xor16(&mut self, a: u16, b: u16) -> AluOutputs {
-        todo!()
+        Self::outputs(a ^ b, false, false)
     }
 
     fn complement(&mut self, a: u16) -> AluOutputs {
-        todo!()
+        Self::outputs(!a, false, false)
     }
 
     fn shift16l(&mut self, a: u16, b: u16) -> AluOutputs {
-        todo!()
+        // Only the lower 15 bits are shifted, the bit leaving them is the
+        // carry
+        let wide = ((a & !SIGN_BIT) as u32) << b.min(16);
+        let value = (a & SIGN_BIT) | (wide as u16 & !SIGN_BIT);
+
+        // Overflow when the result is no longer `a` multiplied by `2^b`
+        let product = (a as i16 as i32) << b.min(16);
+        let overflow = product != value as i16 as i32;
+
+        Self::outputs(value, wide & SIGN_BIT as u32 != 0, overflow)
     }
 
     fn shift16r(&mut self, a: u16, b: u16) -> AluOutputs {
-        todo!()
+        // Keep one extra bit below the value to catch the last bit shifted out
+        let wide = ((a as i16 as i32) << 1) >> b.min(17);
+
+        Self::outputs((wide >> 1) as u16, wide & 1 != 0, false)
     }
 
     fn ushift16l(&mut self, a: u16, b: u16) -> AluOutputs {
-        todo!()
+        // Keep one extra bit above the value to catch the last bit shifted out
+        let wide = (a as u32) << b.min(17);
+
+        Self::outputs(wide as u16, wide & 1 << 16 != 0, false)
     }
 
     fn ushift16r(&mut self, a: u16, b: u16) -> AluOutputs {
-        todo!()
+        // Keep one extra bit below the value to catch the last bit shifted out
+        let wide = ((a as u32) << 1) >> b.min(17);
+
+        Self::outputs((wide >> 1) as u16, wide & 1 != 0, false)
     }
 
     fn rot16l(&mut self, a: u16, b: u16) -> AluOutputs {
-        todo!()
+        let value = a.rotate_left(b as u32);
+
+        // The carry is the last bit rotated around into the lowest bit
+        Self::outputs(value, b != 0 && value & 1 != 0, false)
     }
 
     fn rot16r(&mut self, a: u16, b: u16) -> AluOutputs {
-        todo!()
+        let value = a.rotate_right(b as u32);
+
+        // The carry is the last bit rotated around into the highest bit
+        Self::outputs(value, b != 0 && value & SIGN_BIT != 0, false)
     }
 
     fn rot16l_carry(&mut self, a: u16, b: u16, carry: bool) -> AluOutputs {
-        todo!()
+        // Rotate as a 17 bit value with the carry as the highest bit
+        let wide = (carry as u32) << 16 | a as u32;
+        let amount = b as u32 % 17;
+        let rotated = (wide << amount | wide >> (17 - amount)) & 0x1FFFF;
+
+        Self::outputs(rotated as u16, rotated & 1 << 16 != 0, false)
     }
 
     fn rot16r_carry(&mut self, a: u16, b: u16, carry: bool) -> AluOutputs {
-        todo!()
+        // Rotate as a 17 bit value with the carry as the highest bit
+        let wide = (carry as u32) << 16 | a as u32;
+        let amount = b as u32 % 17;
+        let rotated = (wide >> amount | wide << (17 - amount)) & 0x1FFFF;
+
+        Self::outputs(rotated as u16, rotated & 1 << 16 != 0, false)
+    }
+
+    fn mul16(&mut self, a: u16, b: u16) -> (AluOutputs, u16) {
+        let product = a as u32 * b as u32;
+        let high = (product >> 16) as u16;
+
+        (Self::outputs(product as u16, high != 0, high != 0), high)
+    }
+
+    fn imul16(&mut self, a: u16, b: u16) -> (AluOutputs, u16) {
+        let product = a as i16 as i32 * b as i16 as i32;
+        let high = (product >> 16) as u16;
+
+        // The product only fits in the low word if sign extending the low word
+        // gives back the whole product
+        let significant = product != product as i16 as i32;
+
+        (
+            Self::outputs(product as u16, significant, significant),
+            high,
+        )
+    }
+
+    fn div16(&mut self, a: u16, b: u16) -> Option<AluOutputs> {
+        let quotient = a.checked_div(b)?;
+
+        Some(Self::outputs(quotient, false, false))
+    }
+
+    fn idiv16(&mut self, a: u16, b: u16) -> Option<AluOutputs> {
+        if b == 0 {
+            return None;
+        }
+        let (quotient, overflow) = (a as i16).overflowing_div(b as i16);
+
+        Some(Self::outputs(quotient as u16, false, overflow))
+    }
+
+    fn mod16(&mut self, a: u16, b: u16) -> Option<AluOutputs> {
+        let remainder = a.checked_rem(b)?;
+
+        Some(Self::outputs(remainder, false, false))
+    }
+
+    fn imod16(&mut self, a: u16, b: u16) -> Option<AluOutputs> {
+        if b == 0 {
+            return None;
+        }
+        // The remainder of dividing the most negative value by -1 is 0, even
+        // though the quotient wraps around
+        let remainder = (a as i16).wrapping_rem(b as i16);
+
+        Some(Self::outputs(remainder as u16, false, false))
     }
 }
@@ -0,0 +1,143 @@
+use std::collections::HashSet;
+
+// A read or write of a byte of memory by an instruction
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+// The accesses a watchpoint stops on
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchKind {
+    pub fn covers(self, access: Access) -> bool {
+        match self {
+            Self::Read => access == Access::Read,
+            Self::Write => access == Access::Write,
+            Self::ReadWrite => true,
+        }
+    }
+}
+
+// What the byte read or written must be for a watchpoint to stop
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ValueCondition {
+    Any,
+    Equal(u8),
+    NotEqual(u8),
+    // The bits of the byte selected by `mask` equal those bits of `value`
+    Masked { mask: u8, value: u8 },
+}
+
+impl ValueCondition {
+    pub fn holds(self, value: u8) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Equal(expected) => value == expected,
+            Self::NotEqual(expected) => value != expected,
+            Self::Masked {
+                mask,
+                value: expected,
+            } => value & mask == expected & mask,
+        }
+    }
+}
+
+// Stops on accesses to the addresses from `start` to `end`, both included
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub kind: WatchKind,
+    pub condition: ValueCondition,
+}
+
+impl Watchpoint {
+    // Watch a range of addresses for any value
+    pub fn new(start: u16, end: u16, kind: WatchKind) -> Self {
+        Self {
+            start,
+            end,
+            kind,
+            condition: ValueCondition::Any,
+        }
+    }
+
+    pub fn with_condition(mut self, condition: ValueCondition) -> Self {
+        self.condition = condition;
+        self
+    }
+
+    pub fn matches(&self, address: u16, access: Access, value: u8) -> bool {
+        (self.start..=self.end).contains(&address)
+            && self.kind.covers(access)
+            && self.condition.holds(value)
+    }
+}
+
+// The addresses that stop execution before the instruction there runs, and
+// the watchpoints that stop it after an instruction accesses memory
+#[derive(Default)]
+pub struct Breakpoints {
+    breakpoints: HashSet<u16>,
+    watchpoints: Vec<Watchpoint>,
+}
+
+impl Breakpoints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Returns whether there wasn't already a breakpoint at `address`
+    pub fn add_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.insert(address)
+    }
+
+    // Returns whether there was a breakpoint at `address`
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    // Returns whether there was such a watchpoint
+    pub fn remove_watchpoint(&mut self, watchpoint: &Watchpoint) -> bool {
+        let len = self.watchpoints.len();
+        self.watchpoints.retain(|w| w != watchpoint);
+        self.watchpoints.len() != len
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+        self.watchpoints.clear();
+    }
+
+    // These are checked on every instruction and memory access, so they
+    // avoid any work while nothing is set
+
+    pub(crate) fn breakpoint_at(&self, address: u16) -> bool {
+        !self.breakpoints.is_empty() && self.breakpoints.contains(&address)
+    }
+
+    pub(crate) fn watching(&self, address: u16, access: Access, value: u8) -> bool {
+        self.watchpoints
+            .iter()
+            .any(|watchpoint| watchpoint.matches(address, access, value))
+    }
+}
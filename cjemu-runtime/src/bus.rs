@@ -0,0 +1,292 @@
+use crate::{Ram, Rom};
+use cjemu_api::{BusError, Device, ReadableMemory, WritableMemory, ADDRESS_SPACE};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum LayoutError {
+    // The region with this name covers no addresses
+    EmptyRegion(String),
+    // The region with this name extends past the end of the address space
+    OutOfRange(String),
+}
+
+// What happens when an address without a region is accessed
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum UnmappedAccess {
+    // Reads and writes fail with a bus error
+    Fault,
+    // Reads return this value and writes are ignored
+    OpenBus(u8),
+}
+
+// The memory that a region of the address space is mapped to
+pub enum Target {
+    Rom,
+    Ram,
+    Device(Box<dyn Device>),
+}
+
+impl Target {
+    fn size(&self, rom: &Rom, ram: &Ram) -> u32 {
+        match self {
+            Self::Rom => rom.size(),
+            Self::Ram => ram.size(),
+            Self::Device(device) => device.size() as u32,
+        }
+    }
+}
+
+pub struct Region {
+    pub name: String,
+    pub base: u16,
+    // The number of addresses covered, which may be the whole address space
+    pub len: u32,
+    pub target: Target,
+    // Whether the target repeats to fill the region when it is smaller than
+    // the region, rather than leaving the rest of the region unmapped
+    pub mirrored: bool,
+}
+
+impl Region {
+    pub fn new(name: &str, base: u16, len: u32, target: Target) -> Self {
+        Self {
+            name: name.to_owned(),
+            base,
+            len,
+            target,
+            mirrored: false,
+        }
+    }
+
+    pub fn with_mirroring(mut self) -> Self {
+        self.mirrored = true;
+        self
+    }
+
+    pub fn contains(&self, address: u16) -> bool {
+        address >= self.base && ((address - self.base) as u32) < self.len
+    }
+}
+
+pub struct BusLayout {
+    // Regions later in the list take precedence where regions overlap
+    pub regions: Vec<Region>,
+    pub unmapped: UnmappedAccess,
+}
+
+impl BusLayout {
+    // Map a device's window into the address space at `base`, over any
+    // regions already in the layout
+    pub fn with_device(mut self, base: u16, device: Box<dyn Device>) -> Self {
+        let name = device.name().to_owned();
+        let len = device.size() as u32;
+        self.regions
+            .push(Region::new(&name, base, len, Target::Device(device)));
+        self
+    }
+}
+
+impl Default for BusLayout {
+    // ROM in the lower half of the address space and RAM in the upper half
+    fn default() -> Self {
+        Self {
+            regions: vec![
+                Region::new("rom", 0x0000, 0x8000, Target::Rom),
+                Region::new("ram", 0x8000, 0x8000, Target::Ram),
+            ],
+            unmapped: UnmappedAccess::Fault,
+        }
+    }
+}
+
+pub struct MemoryBus {
+    rom: Rom,
+    ram: Ram,
+
+    regions: Vec<Region>,
+    unmapped: UnmappedAccess,
+}
+
+impl MemoryBus {
+    pub fn new(rom: Rom, ram: Ram, layout: BusLayout) -> Result<Self, LayoutError> {
+        for region in &layout.regions {
+            if region.len == 0 {
+                return Err(LayoutError::EmptyRegion(region.name.clone()));
+            }
+            if region.base as u32 + region.len > ADDRESS_SPACE {
+                return Err(LayoutError::OutOfRange(region.name.clone()));
+            }
+        }
+
+        Ok(Self {
+            rom,
+            ram,
+
+            regions: layout.regions,
+            unmapped: layout.unmapped,
+        })
+    }
+
+    pub fn rom(&self) -> &Rom {
+        &self.rom
+    }
+
+    pub fn ram(&self) -> &Ram {
+        &self.ram
+    }
+
+    pub fn rom_mut(&mut self) -> &mut Rom {
+        &mut self.rom
+    }
+
+    pub fn ram_mut(&mut self) -> &mut Ram {
+        &mut self.ram
+    }
+
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    pub fn unmapped(&self) -> UnmappedAccess {
+        self.unmapped
+    }
+
+    // The devices mapped into the address space along with their base
+    // addresses, in the order of their regions
+    pub fn devices(&self) -> impl Iterator<Item = (u16, &dyn Device)> + '_ {
+        self.regions
+            .iter()
+            .filter_map(|region| match &region.target {
+                Target::Device(device) => Some((region.base, device.as_ref())),
+                _ => None,
+            })
+    }
+
+    pub fn devices_mut(&mut self) -> impl Iterator<Item = (u16, &mut Box<dyn Device>)> + '_ {
+        self.regions
+            .iter_mut()
+            .filter_map(|region| match &mut region.target {
+                Target::Device(device) => Some((region.base, device)),
+                _ => None,
+            })
+    }
+
+    // The base address and length of the part of the address space that RAM
+    // is mapped into first, leaving out any mirrors
+    pub fn ram_span(&self) -> Option<(u16, u32)> {
+        self.regions
+            .iter()
+            .find(|region| matches!(region.target, Target::Ram))
+            .map(|region| (region.base, region.len.min(self.ram.size())))
+    }
+
+    // The region `address` is mapped into, if any
+    pub fn region(&self, address: u16) -> Option<&Region> {
+        self.regions
+            .iter()
+            .rev()
+            .find(|region| region.contains(address))
+    }
+
+    pub fn read(&mut self, address: u16) -> Result<u8, BusError> {
+        let byte = match self.resolve(address) {
+            Some((index, offset)) => match &mut self.regions[index].target {
+                Target::Rom => self.rom.byte(offset),
+                Target::Ram => self.ram.byte(offset),
+                Target::Device(device) => Some(device.read(offset).map_err(|err| err.at(address))?),
+            },
+            None => None,
+        };
+
+        self.read_result(address, byte)
+    }
+
+    // Read a byte without any effect on devices
+    pub fn peek(&self, address: u16) -> Result<u8, BusError> {
+        let byte =
+            self.resolve(address)
+                .and_then(|(index, offset)| match &self.regions[index].target {
+                    Target::Rom => self.rom.byte(offset),
+                    Target::Ram => self.ram.byte(offset),
+                    Target::Device(device) => device.peek(offset),
+                });
+
+        self.read_result(address, byte)
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) -> Result<(), BusError> {
+        let written = match self.resolve(address) {
+            Some((index, offset)) => match &mut self.regions[index].target {
+                Target::Rom => return Err(BusError::ReadOnly(address)),
+                Target::Ram => self.ram.set_byte(offset, value),
+                Target::Device(device) => {
+                    device.write(offset, value).map_err(|err| err.at(address))?;
+                    Some(())
+                }
+            },
+            None => None,
+        };
+
+        match (written, self.unmapped) {
+            (Some(()), _) | (None, UnmappedAccess::OpenBus(_)) => Ok(()),
+            (None, UnmappedAccess::Fault) => Err(BusError::Unmapped(address)),
+        }
+    }
+
+    // Advance every device by `cycles` machine cycles, passing on the
+    // interrupt lines they raise
+    pub fn step_devices(&mut self, cycles: u32, mut raise: impl FnMut(u8)) {
+        for region in &mut self.regions {
+            if let Target::Device(device) = &mut region.target {
+                if let Some(line) = device.step(cycles) {
+                    raise(line);
+                }
+            }
+        }
+    }
+
+    // Put every device back into the state it started in
+    pub fn reset_devices(&mut self) {
+        for (_, device) in self.devices_mut() {
+            device.reset();
+        }
+    }
+
+    // Apply the unmapped access behavior when no byte was read
+    fn read_result(&self, address: u16, byte: Option<u8>) -> Result<u8, BusError> {
+        match (byte, self.unmapped) {
+            (Some(byte), _) => Ok(byte),
+            (None, UnmappedAccess::OpenBus(value)) => Ok(value),
+            (None, UnmappedAccess::Fault) => Err(BusError::Unmapped(address)),
+        }
+    }
+
+    // Find the index of the region `address` is mapped into along with the
+    // offset into its target
+    fn resolve(&self, address: u16) -> Option<(usize, u16)> {
+        let index = self
+            .regions
+            .iter()
+            .rposition(|region| region.contains(address))?;
+        let region = &self.regions[index];
+
+        let size = region.target.size(&self.rom, &self.ram);
+        let offset = (address - region.base) as u32;
+        match (offset < size, region.mirrored && size > 0) {
+            (true, _) => Some((index, offset as u16)),
+            (false, true) => Some((index, (offset % size) as u16)),
+            (false, false) => None,
+        }
+    }
+}
+
+// Peeking through the bus, for instruction fetches and tools such as the
+// instruction decoder
+impl ReadableMemory for MemoryBus {
+    fn size(&self) -> u32 {
+        ADDRESS_SPACE
+    }
+
+    fn byte(&self, address: u16) -> Option<u8> {
+        self.peek(address).ok()
+    }
+}
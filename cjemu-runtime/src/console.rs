@@ -0,0 +1,107 @@
+use cjemu_api::{BusError, Device};
+use std::io::Write;
+use std::sync::mpsc;
+
+// What the console does with a byte written to its port
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ConsoleEvent {
+    Print(char),
+    Newline,
+    Backspace,
+    Clear,
+}
+
+impl ConsoleEvent {
+    pub const NEWLINE: u8 = b'\n';
+    pub const BACKSPACE: u8 = 0x08;
+    pub const CLEAR: u8 = 0x0C;
+
+    // Other control bytes are ignored, and the rest are printed as Latin-1
+    // characters
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            Self::NEWLINE => Some(Self::Newline),
+            Self::BACKSPACE => Some(Self::Backspace),
+            Self::CLEAR => Some(Self::Clear),
+            byte if byte.is_ascii_control() => None,
+            byte => Some(Self::Print(byte as char)),
+        }
+    }
+}
+
+// Where the console sends its output
+pub enum ConsoleSink {
+    Channel(mpsc::Sender<ConsoleEvent>),
+    Stdout,
+}
+
+// A character output device with a single word-sized port. Writing the low
+// byte of the port outputs it, while the high byte is ignored so words may be
+// stored to the port directly.
+pub struct Console {
+    sink: ConsoleSink,
+}
+
+impl Console {
+    pub const PORT: u16 = 0;
+    pub const SIZE: u16 = 2;
+
+    pub fn new(sink: ConsoleSink) -> Self {
+        Self { sink }
+    }
+
+    // A console streaming its output over a channel
+    pub fn channel() -> (Self, mpsc::Receiver<ConsoleEvent>) {
+        let (sender, receiver) = mpsc::channel();
+        (Self::new(ConsoleSink::Channel(sender)), receiver)
+    }
+
+    // A console writing straight to the standard output, for headless use
+    pub fn stdout() -> Self {
+        Self::new(ConsoleSink::Stdout)
+    }
+
+    fn output(&mut self, event: ConsoleEvent) {
+        match &self.sink {
+            // The receiving end going away just means nobody is watching
+            ConsoleSink::Channel(sender) => sender.send(event).unwrap_or(()),
+            ConsoleSink::Stdout => {
+                let mut stdout = std::io::stdout();
+                let written = match event {
+                    ConsoleEvent::Print(c) => write!(stdout, "{}", c),
+                    ConsoleEvent::Newline => writeln!(stdout),
+                    // Step back, blank out the character and step back again
+                    ConsoleEvent::Backspace => write!(stdout, "\x08 \x08"),
+                    // Clear the terminal and move the cursor to the top left
+                    ConsoleEvent::Clear => write!(stdout, "\x1b[2J\x1b[H"),
+                };
+                // Likewise for stdout being closed, such as when piped into
+                // a program that has exited
+                written.and_then(|_| stdout.flush()).unwrap_or(());
+            }
+        }
+    }
+}
+
+impl Device for Console {
+    fn name(&self) -> &str {
+        "console"
+    }
+
+    fn size(&self) -> u16 {
+        Self::SIZE
+    }
+
+    fn read(&mut self, offset: u16) -> Result<u8, BusError> {
+        Err(BusError::WriteOnly(offset))
+    }
+
+    fn write(&mut self, offset: u16, value: u8) -> Result<(), BusError> {
+        if offset == Self::PORT {
+            if let Some(event) = ConsoleEvent::from_byte(value) {
+                self.output(event);
+            }
+        }
+        Ok(())
+    }
+}
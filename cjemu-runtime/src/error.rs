@@ -0,0 +1,495 @@
+use crate::{Access, SaveState};
+use cjemu_api::{BusError, DecodeError, ADDRESS_SPACE, IMAGE_VERSION};
+use std::fmt;
+
+// Why the virtual machine stopped, where `pc` is the address of the
+// instruction that was executing and `address` is the address that caused the
+// stop. Breakpoints and watchpoints only pause the machine, while any other
+// error is a fault that stops it for good
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TickError {
+    // The byte at the program counter isn't an opcode
+    IllegalOpcode {
+        pc: u16,
+        address: u16,
+        opcode: u8,
+    },
+    // The register operand at `address` names a register that doesn't exist
+    IllegalRegister {
+        pc: u16,
+        address: u16,
+        register: u8,
+    },
+    // An instruction fetch or memory access reached an unmapped address
+    OutOfBounds {
+        pc: u16,
+        address: u16,
+    },
+    // A store tried to write to ROM
+    RomWrite {
+        pc: u16,
+        address: u16,
+    },
+    // A device refused an access to its window
+    Device {
+        pc: u16,
+        address: u16,
+        error: BusError,
+    },
+    // A push would move the stack pointer below the start of RAM, where
+    // `address` is where the word would have gone
+    StackOverflow {
+        pc: u16,
+        address: u16,
+    },
+    // A pop was attempted while the stack was empty, where `address` is the
+    // stack pointer
+    StackUnderflow {
+        pc: u16,
+        address: u16,
+    },
+    // A division or modulo instruction divided by zero, where `address` is the
+    // address of the instruction
+    DivideByZero {
+        pc: u16,
+        address: u16,
+    },
+    // The machine halted, either with a halt instruction or after an earlier
+    // error, and can't tick any more
+    Halted {
+        pc: u16,
+        address: u16,
+    },
+    // Execution reached a breakpoint, pausing before the instruction at `pc`
+    // ran
+    Breakpoint {
+        pc: u16,
+        address: u16,
+    },
+    // The instruction at `pc` made an access matching a watchpoint, pausing
+    // once it finished, where `value` is the byte read or written
+    Watchpoint {
+        pc: u16,
+        address: u16,
+        access: Access,
+        value: u8,
+    },
+}
+
+impl TickError {
+    pub fn pc(self) -> u16 {
+        match self {
+            Self::IllegalOpcode { pc, .. }
+            | Self::IllegalRegister { pc, .. }
+            | Self::OutOfBounds { pc, .. }
+            | Self::RomWrite { pc, .. }
+            | Self::Device { pc, .. }
+            | Self::StackOverflow { pc, .. }
+            | Self::StackUnderflow { pc, .. }
+            | Self::DivideByZero { pc, .. }
+            | Self::Halted { pc, .. }
+            | Self::Breakpoint { pc, .. }
+            | Self::Watchpoint { pc, .. } => pc,
+        }
+    }
+
+    pub fn address(self) -> u16 {
+        match self {
+            Self::IllegalOpcode { address, .. }
+            | Self::IllegalRegister { address, .. }
+            | Self::OutOfBounds { address, .. }
+            | Self::RomWrite { address, .. }
+            | Self::Device { address, .. }
+            | Self::StackOverflow { address, .. }
+            | Self::StackUnderflow { address, .. }
+            | Self::DivideByZero { address, .. }
+            | Self::Halted { address, .. }
+            | Self::Breakpoint { address, .. }
+            | Self::Watchpoint { address, .. } => address,
+        }
+    }
+
+    // Whether the machine stopped for good, rather than pausing where it can
+    // carry on
+    pub fn is_fault(self) -> bool {
+        !matches!(self, Self::Breakpoint { .. } | Self::Watchpoint { .. })
+    }
+}
+
+impl fmt::Display for TickError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::IllegalOpcode { pc, opcode, .. } => {
+                write!(f, "illegal opcode {:#04x} at {:#06x}", opcode, pc)
+            }
+            Self::IllegalRegister { pc, register, .. } => write!(
+                f,
+                "illegal register {} in the instruction at {:#06x}",
+                register, pc
+            ),
+            Self::OutOfBounds { pc, address } => write!(
+                f,
+                "access to unmapped address {:#06x} by the instruction at {:#06x}",
+                address, pc
+            ),
+            Self::RomWrite { pc, address } => write!(
+                f,
+                "write to ROM at {:#06x} by the instruction at {:#06x}",
+                address, pc
+            ),
+            Self::Device { pc, address, error } => {
+                let access = match error {
+                    BusError::ReadOnly(_) => "write to a read-only device address",
+                    BusError::WriteOnly(_) => "read from a write-only device address",
+                    BusError::Unmapped(_) | BusError::Rejected(_) => "refused device access",
+                };
+                write!(
+                    f,
+                    "{} {:#06x} by the instruction at {:#06x}",
+                    access, address, pc
+                )
+            }
+            Self::StackOverflow { pc, address } => write!(
+                f,
+                "stack overflow pushing to {:#06x} by the instruction at {:#06x}",
+                address, pc
+            ),
+            Self::StackUnderflow { pc, address } => write!(
+                f,
+                "stack underflow popping from {:#06x} by the instruction at {:#06x}",
+                address, pc
+            ),
+            Self::DivideByZero { pc, .. } => write!(f, "division by zero at {:#06x}", pc),
+            Self::Halted { pc, .. } => write!(f, "the machine is halted at {:#06x}", pc),
+            Self::Breakpoint { pc, .. } => write!(f, "breakpoint at {:#06x}", pc),
+            Self::Watchpoint {
+                pc,
+                address,
+                access,
+                value,
+            } => {
+                let access = match access {
+                    Access::Read => "read of",
+                    Access::Write => "write of",
+                };
+                write!(
+                    f,
+                    "watchpoint on the {} {:#04x} at {:#06x} by the instruction at {:#06x}",
+                    access, value, address, pc
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for TickError {}
+
+// Why `CJEmuVirtualMachine::run` returned
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum StopReason {
+    // The budget of cycles was used up, or what is left of it is too little
+    // for the next instruction
+    BudgetExhausted,
+    // A breakpoint or watchpoint paused the machine
+    Breakpoint(TickError),
+    // A halt instruction stopped the machine
+    Halted { exit_code: u16 },
+    // The budget of cycles was used up while the machine was idling, waiting
+    // for an interrupt
+    Waiting,
+    // A fault stopped the machine for good
+    Error(TickError),
+}
+
+impl From<TickError> for StopReason {
+    fn from(err: TickError) -> Self {
+        if err.is_fault() {
+            Self::Error(err)
+        } else {
+            Self::Breakpoint(err)
+        }
+    }
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BudgetExhausted => write!(f, "ran out of cycles"),
+            Self::Halted { exit_code } => write!(f, "halted with exit code {}", exit_code),
+            Self::Waiting => write!(f, "ran out of cycles waiting for an interrupt"),
+            Self::Breakpoint(err) | Self::Error(err) => err.fmt(f),
+        }
+    }
+}
+
+// What stopped a tick partway through, before the virtual machine adds where
+// it happened
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum Fault {
+    Decode(DecodeError),
+    Bus(BusError),
+    StackOverflow,
+    StackUnderflow,
+    DivideByZero,
+    Breakpoint,
+}
+
+impl From<DecodeError> for Fault {
+    fn from(err: DecodeError) -> Self {
+        Self::Decode(err)
+    }
+}
+
+impl From<BusError> for Fault {
+    fn from(err: BusError) -> Self {
+        Self::Bus(err)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MemoryError {
+    // A memory container must hold at least one byte
+    Empty,
+    // A memory container can't hold more bytes than the address space, which
+    // this many bytes would exceed
+    TooLarge(usize),
+}
+
+impl MemoryError {
+    pub(crate) fn check_size(size: usize) -> Result<(), Self> {
+        if size == 0 {
+            Err(Self::Empty)
+        } else if size > ADDRESS_SPACE as usize {
+            Err(Self::TooLarge(size))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Empty => write!(f, "memory must hold at least one byte"),
+            Self::TooLarge(size) => write!(
+                f,
+                "{} bytes of memory don't fit in the {} byte address space",
+                size, ADDRESS_SPACE
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MemoryError {}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ImageError {
+    // The image ended before the field at this offset
+    Truncated(usize),
+    // The image doesn't start with the magic number, but these bytes
+    BadMagic([u8; 4]),
+    // The image is in a version of the format that isn't supported
+    UnsupportedVersion(u16),
+    // The checksum stored in the image doesn't match the one calculated
+    ChecksumMismatch { stored: u32, calculated: u32 },
+    // This many bytes were left between the last segment and the checksum
+    TrailingData(usize),
+    // The image has more segments than its header can count
+    TooManySegments(usize),
+    // The segment at this index extends past the end of the address space
+    SegmentOutOfRange(usize),
+    // The segments at these indices cover some of the same addresses
+    SegmentOverlap(usize, usize),
+    // The ROM described by the image can't be created
+    Memory(MemoryError),
+}
+
+impl From<MemoryError> for ImageError {
+    fn from(err: MemoryError) -> Self {
+        Self::Memory(err)
+    }
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated(offset) => write!(f, "image ends early at offset {}", offset),
+            Self::BadMagic(_) => write!(f, "not a ROM image"),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "image version {} isn't supported, only version {}",
+                version, IMAGE_VERSION
+            ),
+            Self::ChecksumMismatch { stored, calculated } => write!(
+                f,
+                "image checksum is {:#010x} but its contents add up to {:#010x}",
+                stored, calculated
+            ),
+            Self::TrailingData(len) => write!(f, "image has {} unexpected bytes at its end", len),
+            Self::TooManySegments(count) => write!(
+                f,
+                "image has {} segments, more than the {} it can hold",
+                count,
+                u16::MAX
+            ),
+            Self::SegmentOutOfRange(index) => write!(
+                f,
+                "image segment {} extends past the end of the address space",
+                index
+            ),
+            Self::SegmentOverlap(first, second) => {
+                write!(f, "image segments {} and {} overlap", first, second)
+            }
+            Self::Memory(err) => write!(f, "image doesn't fit in memory: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ImageError {}
+
+// What is wrong with a record in an Intel HEX or S-record file
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RecordError {
+    // The record doesn't start with the start code of the format
+    MissingStartCode,
+    // The record has characters that aren't hexadecimal digits, or an odd
+    // number of them
+    InvalidHex,
+    // The record is shorter or longer than its byte count says
+    BadLength,
+    // The checksum at the end of the record doesn't match its contents
+    ChecksumMismatch { stored: u8, calculated: u8 },
+    // The record type isn't one the format defines
+    UnknownType(u8),
+    // The record places data or the entry point at this address, outside of
+    // the address space
+    AddressOutOfRange(u32),
+    // The number of data records counted so far doesn't match the count
+    // stored in this record
+    CountMismatch { stored: u32, counted: u32 },
+    // The file ended without an end of file or termination record
+    MissingEnd,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum HexError {
+    // The record on this line, counting from 1, is invalid
+    Record { line: usize, error: RecordError },
+    // The records make up an invalid image, such as with overlapping data
+    Image(ImageError),
+}
+
+impl From<ImageError> for HexError {
+    fn from(err: ImageError) -> Self {
+        Self::Image(err)
+    }
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::MissingStartCode => write!(f, "record doesn't start with a start code"),
+            Self::InvalidHex => write!(f, "record isn't made of pairs of hexadecimal digits"),
+            Self::BadLength => write!(f, "record length doesn't match its byte count"),
+            Self::ChecksumMismatch { stored, calculated } => write!(
+                f,
+                "record checksum is {:#04x} but its contents add up to {:#04x}",
+                stored, calculated
+            ),
+            Self::UnknownType(record_type) => write!(f, "unknown record type {}", record_type),
+            Self::AddressOutOfRange(address) => write!(
+                f,
+                "record address {:#x} is outside of the address space",
+                address
+            ),
+            Self::CountMismatch { stored, counted } => write!(
+                f,
+                "record count is {} but {} data records came before it",
+                stored, counted
+            ),
+            Self::MissingEnd => write!(f, "file ends without an end record"),
+        }
+    }
+}
+
+impl std::error::Error for RecordError {}
+
+impl fmt::Display for HexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Record { line, error } => write!(f, "line {}: {}", line, error),
+            Self::Image(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for HexError {}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SaveStateError {
+    // The save state ended before the field at this offset
+    Truncated(usize),
+    // The save state doesn't start with the magic number, but these bytes
+    BadMagic([u8; 4]),
+    // The save state is in a version of the format that isn't supported
+    UnsupportedVersion(u16),
+    // The checksum stored in the save state doesn't match the one calculated
+    ChecksumMismatch { stored: u32, calculated: u32 },
+    // This many bytes were left between the last device and the checksum
+    TrailingData(usize),
+    // The field at this offset holds a value that can't be restored
+    InvalidField(usize),
+    // The save state has a different amount of RAM than the virtual machine
+    RamSizeMismatch { saved: usize, expected: usize },
+    // The device at this index isn't the same as in the virtual machine, or
+    // the number of devices is different
+    DeviceMismatch(usize),
+    // The device at this index rejected its saved state
+    DeviceState(usize),
+    // The saved ROM can't be created
+    Memory(MemoryError),
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated(offset) => write!(f, "save state ends early at offset {}", offset),
+            Self::BadMagic(_) => write!(f, "not a save state"),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "save state version {} isn't supported, only version {}",
+                version,
+                SaveState::VERSION
+            ),
+            Self::ChecksumMismatch { .. } => write!(f, "save state is corrupt"),
+            Self::TrailingData(len) => {
+                write!(f, "save state has {} unexpected bytes at its end", len)
+            }
+            Self::InvalidField(offset) => {
+                write!(f, "save state has an invalid value at offset {}", offset)
+            }
+            Self::RamSizeMismatch { saved, expected } => write!(
+                f,
+                "save state has {} bytes of RAM rather than {}",
+                saved, expected
+            ),
+            Self::DeviceMismatch(index) => write!(
+                f,
+                "save state device {} doesn't match the virtual machine",
+                index
+            ),
+            Self::DeviceState(index) => {
+                write!(f, "save state device {} has an invalid state", index)
+            }
+            Self::Memory(err) => write!(f, "save state has an invalid ROM: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SaveStateError {}
+
+impl From<MemoryError> for SaveStateError {
+    fn from(err: MemoryError) -> Self {
+        Self::Memory(err)
+    }
+}
@@ -0,0 +1,331 @@
+// Intel HEX, Motorola S-record and raw binary conversions for images, for
+// exchanging programs and memory contents with other tools
+use crate::{HexError, ImageError, RecordError, RomImage, Segment};
+use cjemu_api::{ReadableMemory, ADDRESS_SPACE};
+use std::fmt::Write;
+
+// The most data bytes written in a single record
+const RECORD_DATA_SIZE: usize = 16;
+
+// Intel HEX record types
+const IHEX_DATA: u8 = 0x00;
+const IHEX_END: u8 = 0x01;
+const IHEX_SEGMENT_ADDRESS: u8 = 0x02;
+const IHEX_START_SEGMENT: u8 = 0x03;
+const IHEX_LINEAR_ADDRESS: u8 = 0x04;
+const IHEX_START_LINEAR: u8 = 0x05;
+
+// The module name written to the header of S-record files
+const SRECORD_HEADER: &[u8] = b"cjemu";
+
+impl RomImage {
+    // An image of raw bytes placed at `address`, which is also the entry point
+    pub fn from_binary(data: Vec<u8>, address: u16) -> Self {
+        Self::new(address, address).with_segment(0, data)
+    }
+
+    // An image of `len` bytes of `memory` from `address`, stopping early at
+    // the end of the memory or of the address space
+    pub fn from_memory(memory: &impl ReadableMemory, address: u16, len: u32) -> Self {
+        let len = len.min(ADDRESS_SPACE - address as u32);
+        let data = (address as u32..address as u32 + len)
+            .map_while(|address| memory.byte(address as u16))
+            .collect();
+        Self::new(address, 0).with_segment(address, data)
+    }
+
+    pub fn from_intel_hex(text: &str) -> Result<Self, HexError> {
+        let mut builder = SegmentBuilder::default();
+        let mut base = 0;
+        let mut entry_point = None;
+        let mut ended = false;
+
+        for (line, record) in numbered_lines(text) {
+            let record_error = |error| HexError::Record { line, error };
+            let bytes = decode_record(record, ":").map_err(record_error)?;
+
+            // The byte count, address and type come before the data, and the
+            // checksum after it
+            if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+                return Err(record_error(RecordError::BadLength));
+            }
+            let (body, checksum) = bytes.split_at(bytes.len() - 1);
+            let calculated = intel_hex_checksum(body);
+            if checksum[0] != calculated {
+                return Err(record_error(RecordError::ChecksumMismatch {
+                    stored: checksum[0],
+                    calculated,
+                }));
+            }
+
+            let address = u16::from_be_bytes([body[1], body[2]]) as u32;
+            let data = &body[4..];
+            let data_word = |len| match data.len() == len {
+                true => Ok(data.iter().fold(0, |word, &byte| word << 8 | byte as u32)),
+                false => Err(record_error(RecordError::BadLength)),
+            };
+            match body[3] {
+                IHEX_DATA => builder.push(base + address, data).map_err(record_error)?,
+                IHEX_END => {
+                    ended = true;
+                    break;
+                }
+                IHEX_SEGMENT_ADDRESS => base = data_word(2)? << 4,
+                IHEX_LINEAR_ADDRESS => base = data_word(2)? << 16,
+                IHEX_START_SEGMENT => {
+                    let start = data_word(4)?;
+                    let start = ((start >> 16) << 4) + (start & 0xFFFF);
+                    entry_point = Some(check_entry_point(start).map_err(record_error)?);
+                }
+                IHEX_START_LINEAR => {
+                    let start = data_word(4)?;
+                    entry_point = Some(check_entry_point(start).map_err(record_error)?);
+                }
+                record_type => return Err(record_error(RecordError::UnknownType(record_type))),
+            }
+        }
+        if !ended {
+            return Err(HexError::Record {
+                line: text.lines().count() + 1,
+                error: RecordError::MissingEnd,
+            });
+        }
+
+        builder.finish(entry_point)
+    }
+
+    pub fn to_intel_hex(&self) -> Result<String, ImageError> {
+        self.validate()?;
+
+        let mut text = String::new();
+        for (address, data) in self.records() {
+            let mut record = vec![data.len() as u8];
+            record.extend(&address.to_be_bytes());
+            record.push(IHEX_DATA);
+            record.extend(data);
+            write_record(&mut text, ":", &record, intel_hex_checksum(&record));
+        }
+
+        let mut record = vec![4, 0, 0, IHEX_START_LINEAR];
+        record.extend(&(self.entry_point as u32).to_be_bytes());
+        write_record(&mut text, ":", &record, intel_hex_checksum(&record));
+        let record = [0, 0, 0, IHEX_END];
+        write_record(&mut text, ":", &record, intel_hex_checksum(&record));
+
+        Ok(text)
+    }
+
+    pub fn from_srecord(text: &str) -> Result<Self, HexError> {
+        let mut builder = SegmentBuilder::default();
+        let mut entry_point = None;
+        let mut data_records = 0;
+        let mut ended = false;
+
+        for (line, record) in numbered_lines(text) {
+            let record_error = |error| HexError::Record { line, error };
+            let record_type = match record.as_bytes().get(..2) {
+                Some(&[b'S', digit]) if digit.is_ascii_digit() => digit - b'0',
+                _ => return Err(record_error(RecordError::MissingStartCode)),
+            };
+            let bytes = decode_record(&record[2..], "").map_err(record_error)?;
+
+            // The byte count covers the address, data and checksum after it
+            if bytes.len() < 2 || bytes.len() != bytes[0] as usize + 1 {
+                return Err(record_error(RecordError::BadLength));
+            }
+            let (body, checksum) = bytes.split_at(bytes.len() - 1);
+            let calculated = srecord_checksum(body);
+            if checksum[0] != calculated {
+                return Err(record_error(RecordError::ChecksumMismatch {
+                    stored: checksum[0],
+                    calculated,
+                }));
+            }
+
+            let address_size = match record_type {
+                0 | 1 | 5 | 9 => 2,
+                2 | 6 | 8 => 3,
+                3 | 7 => 4,
+                record_type => return Err(record_error(RecordError::UnknownType(record_type))),
+            };
+            if body.len() < 1 + address_size {
+                return Err(record_error(RecordError::BadLength));
+            }
+            let (address, data) = body[1..].split_at(address_size);
+            let address = address
+                .iter()
+                .fold(0, |word, &byte| word << 8 | byte as u32);
+
+            match record_type {
+                // The header holds nothing needed for the image
+                0 => {}
+                1..=3 => {
+                    builder.push(address, data).map_err(record_error)?;
+                    data_records += 1;
+                }
+                5 | 6 if address != data_records => {
+                    return Err(record_error(RecordError::CountMismatch {
+                        stored: address,
+                        counted: data_records,
+                    }))
+                }
+                5 | 6 => {}
+                _ => {
+                    entry_point = Some(check_entry_point(address).map_err(record_error)?);
+                    ended = true;
+                    break;
+                }
+            }
+        }
+        if !ended {
+            return Err(HexError::Record {
+                line: text.lines().count() + 1,
+                error: RecordError::MissingEnd,
+            });
+        }
+
+        builder.finish(entry_point)
+    }
+
+    pub fn to_srecord(&self) -> Result<String, ImageError> {
+        self.validate()?;
+
+        let mut text = String::new();
+        let srecord = |text: &mut String, record_type: &str, address: u16, data: &[u8]| {
+            let mut record = vec![(data.len() + 3) as u8];
+            record.extend(&address.to_be_bytes());
+            record.extend(data);
+            write_record(text, record_type, &record, srecord_checksum(&record));
+        };
+
+        srecord(&mut text, "S0", 0, SRECORD_HEADER);
+        let mut data_records = 0u32;
+        for (address, data) in self.records() {
+            srecord(&mut text, "S1", address, data);
+            data_records += 1;
+        }
+        // The count record is optional, and can only count so many records
+        if data_records <= u16::MAX as u32 {
+            srecord(&mut text, "S5", data_records as u16, &[]);
+        }
+        srecord(&mut text, "S9", self.entry_point, &[]);
+
+        Ok(text)
+    }
+
+    // The address and data of each data record needed for the segments
+    fn records(&self) -> impl Iterator<Item = (u16, &[u8])> {
+        self.segments.iter().flat_map(move |segment| {
+            let start = self.load_address.wrapping_add(segment.offset);
+            segment
+                .data
+                .chunks(RECORD_DATA_SIZE)
+                .enumerate()
+                .map(move |(i, chunk)| (start.wrapping_add((i * RECORD_DATA_SIZE) as u16), chunk))
+        })
+    }
+}
+
+// Collects data records into segments, joining records that follow on from
+// each other
+#[derive(Default)]
+struct SegmentBuilder {
+    segments: Vec<Segment>,
+}
+
+impl SegmentBuilder {
+    fn push(&mut self, address: u32, data: &[u8]) -> Result<(), RecordError> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        // Records can place data anywhere in a 32-bit address space, so check
+        // the start before the end to avoid overflowing
+        if address >= ADDRESS_SPACE || data.len() as u32 > ADDRESS_SPACE - address {
+            return Err(RecordError::AddressOutOfRange(address));
+        }
+
+        match self.segments.last_mut() {
+            Some(last) if last.offset as u32 + last.data.len() as u32 == address => {
+                last.data.extend(data)
+            }
+            _ => self.segments.push(Segment {
+                offset: address as u16,
+                data: data.to_vec(),
+            }),
+        }
+        Ok(())
+    }
+
+    // Make an image at address 0 from the segments, starting at the entry
+    // point if there is one or else at the first segment
+    fn finish(self, entry_point: Option<u16>) -> Result<RomImage, HexError> {
+        let entry_point = entry_point
+            .unwrap_or_else(|| self.segments.first().map_or(0, |segment| segment.offset));
+
+        let image = RomImage {
+            entry_point,
+            load_address: 0,
+            segments: self.segments,
+        };
+        image.validate()?;
+        Ok(image)
+    }
+}
+
+fn check_entry_point(address: u32) -> Result<u16, RecordError> {
+    if address < ADDRESS_SPACE {
+        Ok(address as u16)
+    } else {
+        Err(RecordError::AddressOutOfRange(address))
+    }
+}
+
+// The non-empty lines of a file, trimmed and numbered from 1
+fn numbered_lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty())
+}
+
+// Decode the hexadecimal digits of a record after its start code
+fn decode_record(record: &str, start_code: &str) -> Result<Vec<u8>, RecordError> {
+    if !record.starts_with(start_code) {
+        return Err(RecordError::MissingStartCode);
+    }
+    let digits = &record.as_bytes()[start_code.len()..];
+    if digits.len() % 2 == 1 {
+        return Err(RecordError::InvalidHex);
+    }
+
+    digits
+        .chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or(RecordError::InvalidHex)
+        })
+        .collect()
+}
+
+fn write_record(text: &mut String, start_code: &str, record: &[u8], checksum: u8) {
+    text.push_str(start_code);
+    for byte in record.iter().chain(&[checksum]) {
+        write!(text, "{:02X}", byte).expect("failed to format record");
+    }
+    text.push('\n');
+}
+
+// The two's complement of the sum of the bytes
+fn intel_hex_checksum(bytes: &[u8]) -> u8 {
+    bytes
+        .iter()
+        .fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+        .wrapping_neg()
+}
+
+// The ones' complement of the sum of the bytes
+fn srecord_checksum(bytes: &[u8]) -> u8 {
+    !bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+}
@@ -0,0 +1,207 @@
+use crate::{ImageError, SaveStateError};
+use cjemu_api::{ADDRESS_SPACE, IMAGE_MAGIC, IMAGE_VERSION};
+use std::convert::TryInto;
+
+// The bytes before the first segment
+const HEADER_SIZE: usize = 12;
+// The bytes before the data of a segment
+const SEGMENT_HEADER_SIZE: usize = 6;
+const CHECKSUM_SIZE: usize = 4;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Segment {
+    // Where the data goes, relative to the load address of the image
+    pub offset: u16,
+    pub data: Vec<u8>,
+}
+
+// A program in the ROM image format described by the specification
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RomImage {
+    pub entry_point: u16,
+    pub load_address: u16,
+    pub segments: Vec<Segment>,
+}
+
+impl RomImage {
+    pub fn new(entry_point: u16, load_address: u16) -> Self {
+        Self {
+            entry_point,
+            load_address,
+            segments: Vec::new(),
+        }
+    }
+
+    pub fn with_segment(mut self, offset: u16, data: Vec<u8>) -> Self {
+        self.segments.push(Segment { offset, data });
+        self
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, ImageError> {
+        let mut reader = Reader::new(bytes);
+
+        let magic = reader.take(IMAGE_MAGIC.len())?;
+        if magic != IMAGE_MAGIC {
+            return Err(ImageError::BadMagic(magic.try_into().unwrap()));
+        }
+        let version = reader.u16()?;
+        if version != IMAGE_VERSION {
+            return Err(ImageError::UnsupportedVersion(version));
+        }
+
+        // Check the whole image before trusting anything else in it
+        if bytes.len() < HEADER_SIZE + CHECKSUM_SIZE {
+            return Err(ImageError::Truncated(bytes.len()));
+        }
+        let (body, checksum) = bytes.split_at(bytes.len() - CHECKSUM_SIZE);
+        let stored = u32::from_le_bytes(checksum.try_into().unwrap());
+        let calculated = crc32(body);
+        if stored != calculated {
+            return Err(ImageError::ChecksumMismatch { stored, calculated });
+        }
+        reader.bytes = body;
+
+        let entry_point = reader.u16()?;
+        let load_address = reader.u16()?;
+        let segment_count = reader.u16()?;
+        let mut image = Self::new(entry_point, load_address);
+        for _ in 0..segment_count {
+            let offset = reader.u16()?;
+            let len = reader.u32()?;
+            let data = reader.take(len as usize)?.to_vec();
+            image.segments.push(Segment { offset, data });
+        }
+        if reader.position < body.len() {
+            return Err(ImageError::TrailingData(body.len() - reader.position));
+        }
+
+        image.validate()?;
+        Ok(image)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ImageError> {
+        self.validate()?;
+
+        let data_size: usize = self.segments.iter().map(|s| s.data.len()).sum();
+        let mut bytes = Vec::with_capacity(
+            HEADER_SIZE + SEGMENT_HEADER_SIZE * self.segments.len() + data_size + CHECKSUM_SIZE,
+        );
+        bytes.extend(&IMAGE_MAGIC);
+        bytes.extend(&IMAGE_VERSION.to_le_bytes());
+        bytes.extend(&self.entry_point.to_le_bytes());
+        bytes.extend(&self.load_address.to_le_bytes());
+        bytes.extend(&(self.segments.len() as u16).to_le_bytes());
+        for segment in &self.segments {
+            bytes.extend(&segment.offset.to_le_bytes());
+            bytes.extend(&(segment.data.len() as u32).to_le_bytes());
+            bytes.extend(&segment.data);
+        }
+        bytes.extend(&crc32(&bytes).to_le_bytes());
+
+        Ok(bytes)
+    }
+
+    // The number of bytes of ROM needed to hold every segment
+    pub fn rom_size(&self) -> u32 {
+        self.segments
+            .iter()
+            .map(|segment| self.segment_span(segment).1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    // The address of the first byte of a segment and the address just past
+    // its last byte, which may be past the end of the address space
+    fn segment_span(&self, segment: &Segment) -> (u32, u32) {
+        let start = self.load_address as u32 + segment.offset as u32;
+        (start, start + segment.data.len() as u32)
+    }
+
+    // Make sure the segments fit in the address space without overlapping
+    pub fn validate(&self) -> Result<(), ImageError> {
+        if self.segments.len() > u16::MAX as usize {
+            return Err(ImageError::TooManySegments(self.segments.len()));
+        }
+
+        for (i, segment) in self.segments.iter().enumerate() {
+            let (start, end) = self.segment_span(segment);
+            if segment.data.len() > ADDRESS_SPACE as usize || end > ADDRESS_SPACE {
+                return Err(ImageError::SegmentOutOfRange(i));
+            }
+            for (j, other) in self.segments.iter().enumerate().take(i) {
+                let (other_start, other_end) = self.segment_span(other);
+                if start < other_end && other_start < end {
+                    return Err(ImageError::SegmentOverlap(j, i));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Reading past the end of a file at this offset
+pub(crate) struct Truncated(pub(crate) usize);
+
+impl From<Truncated> for ImageError {
+    fn from(Truncated(offset): Truncated) -> Self {
+        Self::Truncated(offset)
+    }
+}
+
+impl From<Truncated> for SaveStateError {
+    fn from(Truncated(offset): Truncated) -> Self {
+        Self::Truncated(offset)
+    }
+}
+
+// Reads little-endian fields from the start of an image or save state
+pub(crate) struct Reader<'a> {
+    pub(crate) bytes: &'a [u8],
+    pub(crate) position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    pub(crate) fn take(&mut self, len: usize) -> Result<&'a [u8], Truncated> {
+        let field = self
+            .bytes
+            .get(self.position..)
+            .and_then(|rest| rest.get(..len))
+            .ok_or(Truncated(self.position))?;
+        self.position += len;
+        Ok(field)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, Truncated> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, Truncated> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, Truncated> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, Truncated> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+// The CRC-32 used by zip and PNG files, one bit at a time
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
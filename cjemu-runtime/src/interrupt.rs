@@ -0,0 +1,93 @@
+use cjemu_api::{IRQ_LINES, VECTOR_TABLE};
+
+pub struct InterruptController {
+    enabled: bool,
+    // A set bit masks the line of the same number
+    mask: u8,
+    // A set bit marks the line of the same number as raised
+    pending: u8,
+}
+
+impl InterruptController {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            mask: 0,
+            pending: 0,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn mask(&self) -> u8 {
+        self.mask
+    }
+
+    pub fn set_mask(&mut self, mask: u8) {
+        self.mask = mask;
+    }
+
+    pub fn pending(&self) -> u8 {
+        self.pending
+    }
+
+    pub fn set_pending(&mut self, pending: u8) {
+        self.pending = pending;
+    }
+
+    pub fn raise(&mut self, line: u8) -> Option<()> {
+        if line < IRQ_LINES {
+            self.pending |= 1 << line;
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    // The highest priority line that is raised and unmasked, as long as
+    // interrupts are enabled
+    pub fn next(&self) -> Option<u8> {
+        if self.enabled {
+            self.highest_raised()
+        } else {
+            None
+        }
+    }
+
+    // The highest priority line that is raised and unmasked, even while
+    // interrupts are disabled, which wakes a waiting virtual machine
+    pub fn highest_raised(&self) -> Option<u8> {
+        let active = self.pending & !self.mask;
+        if active != 0 {
+            Some(active.trailing_zeros() as u8)
+        } else {
+            None
+        }
+    }
+
+    pub fn raised(&self) -> bool {
+        self.highest_raised().is_some()
+    }
+
+    // Clear the line once its interrupt is being handled
+    pub fn acknowledge(&mut self, line: u8) {
+        self.pending &= !(1 << line);
+    }
+
+    // The address within the vector table of the handler address for `line`
+    pub fn vector(line: u8) -> u16 {
+        VECTOR_TABLE + 2 * line as u16
+    }
+}
+
+impl Default for InterruptController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,187 @@
+use cjemu_api::{BusError, Device};
+use std::collections::VecDeque;
+use std::sync::mpsc;
+
+// The host side of a keyboard, which queues key presses into the device
+#[derive(Clone)]
+pub struct KeyboardInput {
+    sender: mpsc::Sender<u8>,
+}
+
+impl KeyboardInput {
+    pub fn press(&self, byte: u8) {
+        // The device going away just means nobody is listening
+        self.sender.send(byte).unwrap_or(());
+    }
+
+    // Queue every character of `text` as Latin-1, such as when pasting
+    pub fn type_text(&self, text: &str) {
+        for c in text.chars() {
+            match c {
+                // Hosts send carriage returns for the enter key
+                '\r' => self.press(b'\n'),
+                c if (c as u32) <= 0xFF => self.press(c as u8),
+                _ => self.press(b'?'),
+            }
+        }
+    }
+}
+
+// A keyboard input device with word-sized registers:
+//
+// | Offset | Register | Access                                           |
+// |--------|----------|--------------------------------------------------|
+// | 0      | Data     | Reading the low byte pops the next key, or 0     |
+// | 2      | Status   | Status bits, reading clears the overflow bit     |
+// | 4      | Control  | Control bits                                     |
+pub struct Keyboard {
+    receiver: mpsc::Receiver<u8>,
+    fifo: VecDeque<u8>,
+    overflowed: bool,
+    interrupt_enabled: bool,
+    irq: Option<u8>,
+}
+
+impl Keyboard {
+    pub const DATA: u16 = 0;
+    pub const STATUS: u16 = 2;
+    pub const CONTROL: u16 = 4;
+    pub const SIZE: u16 = 6;
+
+    // Status bits
+    pub const STATUS_READY: u8 = 1 << 0;
+    pub const STATUS_OVERFLOW: u8 = 1 << 1;
+    // Control bits
+    pub const CONTROL_INTERRUPT: u8 = 1 << 0;
+
+    // Keys beyond this many waiting to be read are dropped
+    pub const CAPACITY: usize = 256;
+
+    // Create a keyboard that can raise the interrupt `irq` when a key is
+    // pressed, along with the input feeding it
+    pub fn channel(irq: Option<u8>) -> (Self, KeyboardInput) {
+        let (sender, receiver) = mpsc::channel();
+        let keyboard = Self {
+            receiver,
+            fifo: VecDeque::with_capacity(Self::CAPACITY),
+            overflowed: false,
+            interrupt_enabled: false,
+            irq,
+        };
+        (keyboard, KeyboardInput { sender })
+    }
+
+    // Move keys from the input into the FIFO, returning whether any arrived
+    fn receive(&mut self) -> bool {
+        let mut received = false;
+        for byte in self.receiver.try_iter() {
+            received = true;
+            if self.fifo.len() < Self::CAPACITY {
+                self.fifo.push_back(byte);
+            } else {
+                self.overflowed = true;
+            }
+        }
+        received
+    }
+
+    fn status(&self) -> u8 {
+        let mut status = 0;
+        if !self.fifo.is_empty() {
+            status |= Self::STATUS_READY;
+        }
+        if self.overflowed {
+            status |= Self::STATUS_OVERFLOW;
+        }
+        status
+    }
+
+    fn control(&self) -> u8 {
+        if self.interrupt_enabled {
+            Self::CONTROL_INTERRUPT
+        } else {
+            0
+        }
+    }
+}
+
+impl Device for Keyboard {
+    fn name(&self) -> &str {
+        "keyboard"
+    }
+
+    fn size(&self) -> u16 {
+        Self::SIZE
+    }
+
+    fn read(&mut self, offset: u16) -> Result<u8, BusError> {
+        self.receive();
+        match offset {
+            Self::DATA => Ok(self.fifo.pop_front().unwrap_or(0)),
+            Self::STATUS => {
+                let status = self.status();
+                self.overflowed = false;
+                Ok(status)
+            }
+            _ => Ok(self.peek(offset).unwrap_or(0)),
+        }
+    }
+
+    fn write(&mut self, offset: u16, value: u8) -> Result<(), BusError> {
+        match offset {
+            Self::CONTROL => {
+                self.interrupt_enabled = value & Self::CONTROL_INTERRUPT != 0;
+                Ok(())
+            }
+            // The high bytes of the registers are ignored
+            offset if offset % 2 == 1 => Ok(()),
+            offset => Err(BusError::ReadOnly(offset)),
+        }
+    }
+
+    fn peek(&self, offset: u16) -> Option<u8> {
+        match offset {
+            Self::DATA => Some(self.fifo.front().copied().unwrap_or(0)),
+            Self::STATUS => Some(self.status()),
+            Self::CONTROL => Some(self.control()),
+            _ => Some(0),
+        }
+    }
+
+    fn step(&mut self, _cycles: u32) -> Option<u8> {
+        if self.receive() && self.interrupt_enabled {
+            self.irq
+        } else {
+            None
+        }
+    }
+
+    // Drop every key pressed so far, including those still on their way from
+    // the input
+    fn reset(&mut self) {
+        self.receiver.try_iter().for_each(drop);
+        self.fifo.clear();
+        self.overflowed = false;
+        self.interrupt_enabled = false;
+    }
+
+    // The status and control bits followed by the keys waiting to be read,
+    // leaving out keys still on their way from the input
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = vec![self.status(), self.control()];
+        state.extend(&self.fifo);
+        state
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Option<()> {
+        if state.len() < 2 || state.len() - 2 > Self::CAPACITY {
+            return None;
+        }
+
+        self.overflowed = state[0] & Self::STATUS_OVERFLOW != 0;
+        self.interrupt_enabled = state[1] & Self::CONTROL_INTERRUPT != 0;
+        self.fifo.clear();
+        self.fifo.extend(&state[2..]);
+        Some(())
+    }
+}
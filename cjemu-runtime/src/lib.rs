@@ -1,11 +1,30 @@
 mod alu;
+mod breakpoint;
+mod bus;
+mod console;
+mod error;
+mod hex;
+mod image;
+mod interrupt;
+mod keyboard;
 mod ram;
 mod rom;
+mod save_state;
+mod timer;
 mod virtual_machine;
 
 pub use cjemu_api;
 
 pub use alu::*;
+pub use breakpoint::*;
+pub use bus::*;
+pub use console::*;
+pub use error::*;
+pub use image::*;
+pub use interrupt::*;
+pub use keyboard::*;
 pub use ram::*;
 pub use rom::*;
+pub use save_state::*;
+pub use timer::*;
 pub use virtual_machine::*;
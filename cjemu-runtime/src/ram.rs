@@ -1,32 +1,61 @@
-use cjemu_api::{ReadableMemory, WritableMemory};
+use crate::{ImageError, MemoryError, RomImage};
+use cjemu_api::{ReadableMemory, WritableMemory, ADDRESS_SPACE};
 
 pub struct Ram {
-    max_size: u16,
+    max_size: u32,
     data: Vec<u8>,
 }
 
 impl Ram {
-    pub fn new(default: u8, size: u16) -> Self {
-        Self {
+    pub fn new(default: u8, size: u32) -> Result<Self, MemoryError> {
+        MemoryError::check_size(size as usize)?;
+
+        Ok(Self {
             max_size: size,
             data: vec![default; size as usize],
+        })
+    }
+
+    // Copy the segments of an image into RAM, where the load address is
+    // relative to the start of RAM
+    pub fn load_image(&mut self, image: &RomImage) -> Result<(), ImageError> {
+        image.validate()?;
+
+        for (i, segment) in image.segments.iter().enumerate() {
+            let start = image.load_address as usize + segment.offset as usize;
+            let end = start + segment.data.len();
+            if end > self.max_size as usize {
+                return Err(ImageError::SegmentOutOfRange(i));
+            }
+            self.data[start..end].copy_from_slice(&segment.data);
         }
+
+        Ok(())
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
     }
 }
 
 impl Default for Ram {
+    // A RAM filling the whole address space
     fn default() -> Self {
-        Self::new(0, u16::MAX)
+        Self::new(0, ADDRESS_SPACE).expect("invalid default RAM size")
     }
 }
 
 impl ReadableMemory for Ram {
-    fn size(&self) -> u16 {
+    fn size(&self) -> u32 {
         self.max_size
     }
 
     fn byte(&self, address: u16) -> Option<u8> {
-        if address >= self.max_size {
+        if address as u32 >= self.max_size {
             None
         } else {
             Some(*unsafe { self.data.get_unchecked(address as usize) })
@@ -36,7 +65,7 @@ impl ReadableMemory for Ram {
 
 impl WritableMemory for Ram {
     fn set_byte(&mut self, address: u16, value: u8) -> Option<()> {
-        if address >= self.max_size {
+        if address as u32 >= self.max_size {
             None
         } else {
             *unsafe { self.data.get_unchecked_mut(address as usize) } = value;
@@ -1,32 +1,92 @@
-use cjemu_api::ReadableMemory;
+use crate::{ImageError, MemoryError, RomImage};
+use cjemu_api::{ReadableMemory, ADDRESS_SPACE};
 
 pub struct Rom {
-    max_size: u16,
+    max_size: u32,
     data: Vec<u8>,
+    // Where execution starts
+    entry_point: u16,
 }
 
 impl Rom {
-    pub fn new(default: u8, size: u16) -> Self {
-        Self {
+    pub fn new(default: u8, size: u32) -> Result<Self, MemoryError> {
+        MemoryError::check_size(size as usize)?;
+
+        Ok(Self {
             max_size: size,
             data: vec![default; size as usize],
+            entry_point: 0,
+        })
+    }
+
+    pub fn from_data(data: Vec<u8>) -> Result<Self, MemoryError> {
+        MemoryError::check_size(data.len())?;
+
+        Ok(Self {
+            max_size: data.len() as u32,
+            data,
+            entry_point: 0,
+        })
+    }
+
+    // Place the segments of an image into a ROM just large enough to hold
+    // them, assuming the ROM is mapped at the start of the address space
+    pub fn from_image(image: &RomImage) -> Result<Self, ImageError> {
+        // Images read with `RomImage::parse` have been checked already, but
+        // ones built in memory may not have been
+        image.validate()?;
+
+        let mut rom = Self::new(0, image.rom_size())?;
+        for segment in &image.segments {
+            let start = image.load_address as usize + segment.offset as usize;
+            rom.data[start..start + segment.data.len()].copy_from_slice(&segment.data);
         }
+        rom.entry_point = image.entry_point;
+
+        Ok(rom)
+    }
+
+    // Load a ROM from the bytes of an image
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ImageError> {
+        Self::from_image(&RomImage::parse(bytes)?)
+    }
+
+    pub fn with_entry_point(mut self, entry_point: u16) -> Self {
+        self.entry_point = entry_point;
+        self
+    }
+
+    pub fn entry_point(&self) -> u16 {
+        self.entry_point
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    // Overwrite a byte, which only a debugger may do since the virtual machine
+    // can't write to ROM
+    pub fn patch(&mut self, address: u16, value: u8) -> Option<()> {
+        let byte = self.data.get_mut(address as usize)?;
+        *byte = value;
+        Some(())
     }
 }
 
 impl Default for Rom {
+    // A ROM filling the whole address space
     fn default() -> Self {
-        Self::new(0, u16::MAX)
+        Self::new(0, ADDRESS_SPACE).expect("invalid default ROM size")
     }
 }
 
 impl ReadableMemory for Rom {
-    fn size(&self) -> u16 {
+    fn size(&self) -> u32 {
         self.max_size
     }
 
     fn byte(&self, address: u16) -> Option<u8> {
-        if address >= self.max_size {
+        if address as u32 >= self.max_size {
             None
         } else {
             Some(unsafe { *self.data.get_unchecked(address as usize) })
@@ -0,0 +1,312 @@
+use crate::image::{crc32, Reader};
+use crate::{SaveStateError, TickError};
+use cjemu_api::{AluOutputs, BusError, MachineState};
+use std::convert::TryInto;
+
+// A save state is laid out as little-endian fields:
+//
+// | Size     | Field                                                       |
+// |----------|-------------------------------------------------------------|
+// | 4        | Magic number, `CJSS`                                        |
+// | 2        | Version of the format                                       |
+// | 2        | Program counter                                             |
+// | 2        | Stack pointer                                               |
+// | 8        | The `a`, `b`, `c` and `d` registers                         |
+// | 2        | Value of the last ALU outputs                               |
+// | 2        | Flags of the last ALU outputs                               |
+// | 1        | Whether interrupts are enabled                              |
+// | 1        | Interrupt mask                                              |
+// | 1        | Pending interrupts                                          |
+// | 1        | Machine state: 0 running, 1 waiting, 2 halted               |
+// | 2        | Exit code, when halted                                      |
+// | 6        | Fault: kind (0 for none), pc, address and detail            |
+// | 8        | Cycles                                                      |
+// | 8        | Instructions retired                                        |
+// | 2        | Entry point of the ROM                                      |
+// | 4 + n    | Length and contents of the ROM                              |
+// | 4 + n    | Length and contents of RAM                                  |
+// | 4        | Number of devices, each as:                                 |
+// | 2 + n    |   Length and contents of the device name                    |
+// | 2        |   Base address of the device                                |
+// | 4 + n    |   Length and contents of the device state                   |
+// | 4        | CRC-32 of everything before it                              |
+const HEADER_SIZE: usize = 6;
+const CHECKSUM_SIZE: usize = 4;
+
+// The state of a device mapped into the address space
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DeviceState {
+    pub name: String,
+    pub base: u16,
+    pub data: Vec<u8>,
+}
+
+// Everything needed to put a virtual machine back where it was, apart from
+// its breakpoints and watchpoints
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SaveState {
+    pub pc: u16,
+    pub sp: u16,
+    // The `a`, `b`, `c` and `d` registers
+    pub registers: [u16; 4],
+    pub alu: AluOutputs,
+
+    pub interrupts_enabled: bool,
+    pub interrupt_mask: u8,
+    pub interrupts_pending: u8,
+    // Running, waiting or halted, with any fault kept separately
+    pub state: MachineState,
+    pub fault: Option<TickError>,
+
+    pub cycles: u64,
+    pub instructions_retired: u64,
+
+    // The whole ROM is kept rather than a hash of it, so that the program is
+    // restored along with its patches
+    pub rom: Vec<u8>,
+    pub entry_point: u16,
+    pub ram: Vec<u8>,
+    // In the order of their regions on the bus
+    pub devices: Vec<DeviceState>,
+}
+
+impl SaveState {
+    pub const MAGIC: [u8; 4] = *b"CJSS";
+    pub const VERSION: u16 = 1;
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, SaveStateError> {
+        let mut reader = Reader::new(bytes);
+
+        let magic = reader.take(Self::MAGIC.len())?;
+        if magic != Self::MAGIC {
+            return Err(SaveStateError::BadMagic(magic.try_into().unwrap()));
+        }
+        let version = reader.u16()?;
+        if version != Self::VERSION {
+            return Err(SaveStateError::UnsupportedVersion(version));
+        }
+
+        // Check the whole save state before trusting anything else in it
+        if bytes.len() < HEADER_SIZE + CHECKSUM_SIZE {
+            return Err(SaveStateError::Truncated(bytes.len()));
+        }
+        let (body, checksum) = bytes.split_at(bytes.len() - CHECKSUM_SIZE);
+        let stored = u32::from_le_bytes(checksum.try_into().unwrap());
+        let calculated = crc32(body);
+        if stored != calculated {
+            return Err(SaveStateError::ChecksumMismatch { stored, calculated });
+        }
+        reader.bytes = body;
+
+        let pc = reader.u16()?;
+        let sp = reader.u16()?;
+        let mut registers = [0; 4];
+        for register in &mut registers {
+            *register = reader.u16()?;
+        }
+        let mut alu = AluOutputs {
+            value: reader.u16()?,
+            ..AluOutputs::default()
+        };
+        alu.set_flags(reader.u16()?);
+
+        let interrupts_enabled = reader.u8()? != 0;
+        let interrupt_mask = reader.u8()?;
+        let interrupts_pending = reader.u8()?;
+
+        let state_offset = reader.position;
+        let state = reader.u8()?;
+        let exit_code = reader.u16()?;
+        let state = match state {
+            0 => MachineState::Running,
+            1 => MachineState::Waiting,
+            2 => MachineState::Halted { exit_code },
+            _ => return Err(SaveStateError::InvalidField(state_offset)),
+        };
+        let fault = read_fault(&mut reader)?;
+
+        let cycles = reader.u64()?;
+        let instructions_retired = reader.u64()?;
+
+        let entry_point = reader.u16()?;
+        let rom = read_bytes(&mut reader)?.to_vec();
+        let ram = read_bytes(&mut reader)?.to_vec();
+
+        let device_count = reader.u32()?;
+        let mut devices = Vec::new();
+        for _ in 0..device_count {
+            let name_offset = reader.position;
+            let name_len = reader.u16()?;
+            let name = std::str::from_utf8(reader.take(name_len as usize)?)
+                .map_err(|_| SaveStateError::InvalidField(name_offset))?
+                .to_owned();
+            let base = reader.u16()?;
+            let data = read_bytes(&mut reader)?.to_vec();
+            devices.push(DeviceState { name, base, data });
+        }
+
+        if reader.position < body.len() {
+            return Err(SaveStateError::TrailingData(body.len() - reader.position));
+        }
+
+        Ok(Self {
+            pc,
+            sp,
+            registers,
+            alu,
+
+            interrupts_enabled,
+            interrupt_mask,
+            interrupts_pending,
+            state,
+            fault,
+
+            cycles,
+            instructions_retired,
+
+            rom,
+            entry_point,
+            ram,
+            devices,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend(&Self::MAGIC);
+        bytes.extend(&Self::VERSION.to_le_bytes());
+
+        bytes.extend(&self.pc.to_le_bytes());
+        bytes.extend(&self.sp.to_le_bytes());
+        for register in &self.registers {
+            bytes.extend(&register.to_le_bytes());
+        }
+        bytes.extend(&self.alu.value.to_le_bytes());
+        bytes.extend(&self.alu.flags().to_le_bytes());
+
+        bytes.push(self.interrupts_enabled as u8);
+        bytes.push(self.interrupt_mask);
+        bytes.push(self.interrupts_pending);
+
+        let (state, exit_code) = match self.state {
+            MachineState::Halted { exit_code } => (2, exit_code),
+            MachineState::Waiting => (1, 0),
+            // Faults are saved on their own
+            MachineState::Running | MachineState::Faulted => (0, 0),
+        };
+        bytes.push(state);
+        bytes.extend(&exit_code.to_le_bytes());
+        write_fault(&mut bytes, self.fault);
+
+        bytes.extend(&self.cycles.to_le_bytes());
+        bytes.extend(&self.instructions_retired.to_le_bytes());
+
+        bytes.extend(&self.entry_point.to_le_bytes());
+        write_bytes(&mut bytes, &self.rom);
+        write_bytes(&mut bytes, &self.ram);
+
+        bytes.extend(&(self.devices.len() as u32).to_le_bytes());
+        for device in &self.devices {
+            bytes.extend(&(device.name.len() as u16).to_le_bytes());
+            bytes.extend(device.name.as_bytes());
+            bytes.extend(&device.base.to_le_bytes());
+            write_bytes(&mut bytes, &device.data);
+        }
+
+        bytes.extend(&crc32(&bytes).to_le_bytes());
+        bytes
+    }
+}
+
+// A length followed by that many bytes
+fn write_bytes(bytes: &mut Vec<u8>, data: &[u8]) {
+    bytes.extend(&(data.len() as u32).to_le_bytes());
+    bytes.extend(data);
+}
+
+fn read_bytes<'a>(reader: &mut Reader<'a>) -> Result<&'a [u8], SaveStateError> {
+    let len = reader.u32()?;
+    Ok(reader.take(len as usize)?)
+}
+
+// The kinds of fault, where the detail byte holds the opcode, the register or
+// the kind of bus error
+const ILLEGAL_OPCODE: u8 = 1;
+const ILLEGAL_REGISTER: u8 = 2;
+const OUT_OF_BOUNDS: u8 = 3;
+const ROM_WRITE: u8 = 4;
+const DEVICE: u8 = 5;
+const STACK_OVERFLOW: u8 = 6;
+const STACK_UNDERFLOW: u8 = 7;
+const DIVIDE_BY_ZERO: u8 = 8;
+
+fn write_fault(bytes: &mut Vec<u8>, fault: Option<TickError>) {
+    let (kind, detail) = match fault {
+        None => (0, 0),
+        Some(TickError::IllegalOpcode { opcode, .. }) => (ILLEGAL_OPCODE, opcode),
+        Some(TickError::IllegalRegister { register, .. }) => (ILLEGAL_REGISTER, register),
+        Some(TickError::OutOfBounds { .. }) => (OUT_OF_BOUNDS, 0),
+        Some(TickError::RomWrite { .. }) => (ROM_WRITE, 0),
+        Some(TickError::Device { error, .. }) => {
+            let error = match error {
+                BusError::Unmapped(_) => 0,
+                BusError::ReadOnly(_) => 1,
+                BusError::WriteOnly(_) => 2,
+                BusError::Rejected(_) => 3,
+            };
+            (DEVICE, error)
+        }
+        Some(TickError::StackOverflow { .. }) => (STACK_OVERFLOW, 0),
+        Some(TickError::StackUnderflow { .. }) => (STACK_UNDERFLOW, 0),
+        Some(TickError::DivideByZero { .. }) => (DIVIDE_BY_ZERO, 0),
+        // These don't stop the machine for good, so they are never its fault
+        Some(TickError::Halted { .. })
+        | Some(TickError::Breakpoint { .. })
+        | Some(TickError::Watchpoint { .. }) => (0, 0),
+    };
+    let (pc, address) = fault.map_or((0, 0), |fault| (fault.pc(), fault.address()));
+
+    bytes.push(kind);
+    bytes.extend(&pc.to_le_bytes());
+    bytes.extend(&address.to_le_bytes());
+    bytes.push(detail);
+}
+
+fn read_fault(reader: &mut Reader) -> Result<Option<TickError>, SaveStateError> {
+    let kind_offset = reader.position;
+    let kind = reader.u8()?;
+    let pc = reader.u16()?;
+    let address = reader.u16()?;
+    let detail_offset = reader.position;
+    let detail = reader.u8()?;
+
+    Ok(Some(match kind {
+        0 => return Ok(None),
+        ILLEGAL_OPCODE => TickError::IllegalOpcode {
+            pc,
+            address,
+            opcode: detail,
+        },
+        ILLEGAL_REGISTER => TickError::IllegalRegister {
+            pc,
+            address,
+            register: detail,
+        },
+        OUT_OF_BOUNDS => TickError::OutOfBounds { pc, address },
+        ROM_WRITE => TickError::RomWrite { pc, address },
+        DEVICE => {
+            let error = match detail {
+                0 => BusError::Unmapped(address),
+                1 => BusError::ReadOnly(address),
+                2 => BusError::WriteOnly(address),
+                3 => BusError::Rejected(address),
+                _ => return Err(SaveStateError::InvalidField(detail_offset)),
+            };
+            TickError::Device { pc, address, error }
+        }
+        STACK_OVERFLOW => TickError::StackOverflow { pc, address },
+        STACK_UNDERFLOW => TickError::StackUnderflow { pc, address },
+        DIVIDE_BY_ZERO => TickError::DivideByZero { pc, address },
+        _ => return Err(SaveStateError::InvalidField(kind_offset)),
+    }))
+}
@@ -0,0 +1,233 @@
+use cjemu_api::{BusError, Device};
+
+// A programmable timer counting down once every `prescaler + 1` cycles of the
+// virtual machine, with word-sized registers:
+//
+// | Offset | Register  | Access                                            |
+// |--------|-----------|---------------------------------------------------|
+// | 0      | Counter   | The current count, read-only                      |
+// | 2      | Reload    | The count to start from, where 0 counts 65536     |
+// | 4      | Prescaler | The extra cycles to wait between counts           |
+// | 6      | Control   | Control bits, setting the enable bit (re)starts   |
+// | 8      | Status    | Status bits, reading clears the expired bit       |
+//
+// When the counter reaches 0 the timer expires, raising its interrupt if
+// enabled, and then either stops (one-shot) or starts over from the reload
+// value (periodic).
+pub struct Timer {
+    counter: u16,
+    reload: u16,
+    prescaler: u16,
+    // The cycles waited since the last count
+    prescale_count: u16,
+
+    running: bool,
+    periodic: bool,
+    interrupt_enabled: bool,
+    expired: bool,
+
+    irq: Option<u8>,
+}
+
+impl Timer {
+    pub const COUNTER: u16 = 0;
+    pub const RELOAD: u16 = 2;
+    pub const PRESCALER: u16 = 4;
+    pub const CONTROL: u16 = 6;
+    pub const STATUS: u16 = 8;
+    pub const SIZE: u16 = 10;
+
+    // Control bits
+    pub const CONTROL_ENABLE: u8 = 1 << 0;
+    pub const CONTROL_PERIODIC: u8 = 1 << 1;
+    pub const CONTROL_INTERRUPT: u8 = 1 << 2;
+    // Status bits
+    pub const STATUS_EXPIRED: u8 = 1 << 0;
+
+    // The size of the saved state: the counters followed by the control bits
+    // and the status bits
+    const STATE_SIZE: usize = 10;
+
+    // Create a stopped timer that can raise the interrupt `irq` when it
+    // expires
+    pub fn new(irq: Option<u8>) -> Self {
+        Self {
+            counter: 0,
+            reload: 0,
+            prescaler: 0,
+            prescale_count: 0,
+
+            running: false,
+            periodic: false,
+            interrupt_enabled: false,
+            expired: false,
+
+            irq,
+        }
+    }
+
+    pub fn counter(&self) -> u16 {
+        self.counter
+    }
+
+    pub fn running(&self) -> bool {
+        self.running
+    }
+
+    fn control(&self) -> u8 {
+        let mut control = 0;
+        if self.running {
+            control |= Self::CONTROL_ENABLE;
+        }
+        if self.periodic {
+            control |= Self::CONTROL_PERIODIC;
+        }
+        if self.interrupt_enabled {
+            control |= Self::CONTROL_INTERRUPT;
+        }
+        control
+    }
+
+    fn set_control(&mut self, value: u8) {
+        self.periodic = value & Self::CONTROL_PERIODIC != 0;
+        self.interrupt_enabled = value & Self::CONTROL_INTERRUPT != 0;
+        self.running = value & Self::CONTROL_ENABLE != 0;
+        if self.running {
+            self.counter = self.reload;
+            self.prescale_count = 0;
+        }
+    }
+
+    fn status(&self) -> u8 {
+        if self.expired {
+            Self::STATUS_EXPIRED
+        } else {
+            0
+        }
+    }
+
+    // Count a cycle, returning whether the timer expired
+    fn count(&mut self) -> bool {
+        if !self.running {
+            return false;
+        }
+        if self.prescale_count < self.prescaler {
+            self.prescale_count += 1;
+            return false;
+        }
+        self.prescale_count = 0;
+
+        self.counter = self.counter.wrapping_sub(1);
+        if self.counter != 0 {
+            return false;
+        }
+        self.expired = true;
+        if self.periodic {
+            self.counter = self.reload;
+        } else {
+            self.running = false;
+        }
+        true
+    }
+
+    // Replace the low or high byte of a word-sized register
+    fn set_register_byte(register: &mut u16, offset: u16, value: u8) {
+        let mut bytes = register.to_le_bytes();
+        bytes[(offset % 2) as usize] = value;
+        *register = u16::from_le_bytes(bytes);
+    }
+}
+
+impl Device for Timer {
+    fn name(&self) -> &str {
+        "timer"
+    }
+
+    fn size(&self) -> u16 {
+        Self::SIZE
+    }
+
+    fn read(&mut self, offset: u16) -> Result<u8, BusError> {
+        let byte = self.peek(offset).unwrap_or(0);
+        if offset == Self::STATUS {
+            self.expired = false;
+        }
+        Ok(byte)
+    }
+
+    fn write(&mut self, offset: u16, value: u8) -> Result<(), BusError> {
+        match offset {
+            Self::RELOAD | 3 => Self::set_register_byte(&mut self.reload, offset, value),
+            Self::PRESCALER | 5 => Self::set_register_byte(&mut self.prescaler, offset, value),
+            Self::CONTROL => self.set_control(value),
+            Self::COUNTER | 1 | Self::STATUS => return Err(BusError::ReadOnly(offset)),
+            // The high bytes of the control and status registers are ignored
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn peek(&self, offset: u16) -> Option<u8> {
+        let register = |value: u16| value.to_le_bytes()[(offset % 2) as usize];
+        Some(match offset {
+            Self::COUNTER | 1 => register(self.counter),
+            Self::RELOAD | 3 => register(self.reload),
+            Self::PRESCALER | 5 => register(self.prescaler),
+            Self::CONTROL => self.control(),
+            Self::STATUS => self.status(),
+            _ => 0,
+        })
+    }
+
+    fn step(&mut self, cycles: u32) -> Option<u8> {
+        let mut expired = false;
+        for _ in 0..cycles {
+            expired |= self.count();
+        }
+
+        if expired && self.interrupt_enabled {
+            self.irq
+        } else {
+            None
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new(self.irq);
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = Vec::with_capacity(Self::STATE_SIZE);
+        for counter in &[
+            self.counter,
+            self.reload,
+            self.prescaler,
+            self.prescale_count,
+        ] {
+            state.extend(&counter.to_le_bytes());
+        }
+        state.push(self.control());
+        state.push(self.status());
+        state
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Option<()> {
+        if state.len() != Self::STATE_SIZE {
+            return None;
+        }
+        let word = |i: usize| u16::from_le_bytes([state[i], state[i + 1]]);
+
+        self.counter = word(0);
+        self.reload = word(2);
+        self.prescaler = word(4);
+        self.prescale_count = word(6);
+        // Setting the control bits would restart the count, so set the flags
+        // directly
+        let control = state[8];
+        self.running = control & Self::CONTROL_ENABLE != 0;
+        self.periodic = control & Self::CONTROL_PERIODIC != 0;
+        self.interrupt_enabled = control & Self::CONTROL_INTERRUPT != 0;
+        self.expired = state[9] & Self::STATUS_EXPIRED != 0;
+        Some(())
+    }
+}
@@ -1,54 +1,726 @@
-use crate::{Ram, Rom};
-use cjemu_api::{AluOutputs, VirtualMachine};
+use crate::{
+    Access, Breakpoints, CJEmuAlu, DeviceState, Fault, InterruptController, MemoryBus, Ram, Rom,
+    SaveState, SaveStateError, StopReason, Target, TickError,
+};
+use cjemu_api::{
+    AddressMode, Alu, AluOutputs, BusError, DebugVirtualMachine, DecodeError, Instruction,
+    MachineState, Opcode, Operand, ReadableMemory, Register, VirtualMachine, INTERRUPT_CYCLES,
+};
+use std::convert::TryFrom;
+
+// The cycles taken by each tick spent waiting for an interrupt
+const WAIT_CYCLES: u32 = 1;
 
 pub struct CJEmuVirtualMachine {
+    alu: CJEmuAlu,
     last_alu: AluOutputs,
 
+    pc: u16,
+    sp: u16,
     reg_a: u16,
     reg_b: u16,
+    reg_c: u16,
+    reg_d: u16,
+
+    bus: MemoryBus,
+    // The address just past the end of the stack, wrapping to 0 at the end of
+    // the address space, and the most bytes the stack can hold
+    stack_top: u16,
+    stack_size: u32,
+    // The address RAM is mapped at, where the zero page starts
+    zero_page: u16,
+
+    interrupts: InterruptController,
+    // Whether the machine is running, waiting or halted, apart from faults
+    state: MachineState,
+    // The error that stopped the machine, if it has stopped
+    fault: Option<TickError>,
 
-    rom: Rom,
-    ram: Ram,
+    breakpoints: Breakpoints,
+    // Whether the last tick paused at a breakpoint, which the next tick
+    // should carry on past
+    at_breakpoint: bool,
+    // The first access this tick matching a watchpoint, as the address,
+    // access and byte
+    watch_hit: Option<(u16, Access, u8)>,
+
+    cycles: u64,
+    instructions_retired: u64,
 }
 
 impl CJEmuVirtualMachine {
-    pub fn new(rom_size: u16, ram_size: u16) -> Self {
+    pub fn new(bus: MemoryBus) -> Self {
+        // The stack grows down from the end of RAM
+        let (stack_base, stack_size) = bus.ram_span().unwrap_or((0, 0));
+        let stack_top = (stack_base as u32 + stack_size) as u16;
+
         Self {
+            alu: CJEmuAlu {},
             last_alu: AluOutputs::default(),
 
+            pc: bus.rom().entry_point(),
+            // The stack starts empty
+            sp: stack_top,
             reg_a: 0,
             reg_b: 0,
+            reg_c: 0,
+            reg_d: 0,
+
+            bus,
+            stack_top,
+            stack_size,
+            zero_page: stack_base,
+
+            interrupts: InterruptController::new(),
+            state: MachineState::Running,
+            fault: None,
+
+            breakpoints: Breakpoints::new(),
+            at_breakpoint: false,
+            watch_hit: None,
+
+            cycles: 0,
+            instructions_retired: 0,
+        }
+    }
+
+    // Create a virtual machine with the default memory layout
+    pub fn with_memory(rom: Rom, ram: Ram) -> Self {
+        Self::new(
+            MemoryBus::new(rom, ram, Default::default()).expect("invalid default memory layout"),
+        )
+    }
+
+    pub fn bus(&self) -> &MemoryBus {
+        &self.bus
+    }
+
+    pub fn interrupts(&self) -> &InterruptController {
+        &self.interrupts
+    }
+
+    pub fn fault(&self) -> Option<TickError> {
+        self.fault
+    }
+
+    pub fn breakpoints(&self) -> &Breakpoints {
+        &self.breakpoints
+    }
+
+    pub fn breakpoints_mut(&mut self) -> &mut Breakpoints {
+        &mut self.breakpoints
+    }
+
+    // Tick for up to `budget` cycles, stopping before an instruction that
+    // would take more than what is left of them, or until something stops the
+    // machine first
+    pub fn run(&mut self, budget: u64) -> StopReason {
+        let end = self.cycles.saturating_add(budget);
+        loop {
+            // A stopped machine says why, even with no budget
+            if let Some(fault) = self.fault {
+                return StopReason::Error(fault);
+            }
+            if let MachineState::Halted { exit_code } = self.state {
+                return StopReason::Halted { exit_code };
+            }
+            if self.cycles >= end || self.cycles + self.next_tick_cycles() as u64 > end {
+                return match self.state {
+                    MachineState::Waiting => StopReason::Waiting,
+                    _ => StopReason::BudgetExhausted,
+                };
+            }
+            if let Err(err) = self.perform_tick() {
+                return err.into();
+            }
+        }
+    }
+
+    pub fn save_state(&self) -> SaveState {
+        SaveState {
+            pc: self.pc,
+            sp: self.sp,
+            registers: [self.reg_a, self.reg_b, self.reg_c, self.reg_d],
+            alu: self.last_alu,
+
+            interrupts_enabled: self.interrupts.enabled(),
+            interrupt_mask: self.interrupts.mask(),
+            interrupts_pending: self.interrupts.pending(),
+            state: self.state,
+            fault: self.fault,
+
+            cycles: self.cycles,
+            instructions_retired: self.instructions_retired,
+
+            rom: self.bus.rom().data().to_vec(),
+            entry_point: self.bus.rom().entry_point(),
+            ram: self.bus.ram().data().to_vec(),
+            devices: self
+                .bus
+                .devices()
+                .map(|(base, device)| DeviceState {
+                    name: device.name().to_owned(),
+                    base,
+                    data: device.save_state(),
+                })
+                .collect(),
+        }
+    }
+
+    // Put the machine back into a saved state, which must come from a machine
+    // with the same amount of RAM and the same devices. Nothing changes if
+    // the state can't be loaded. Breakpoints and watchpoints are kept as they
+    // are
+    pub fn load_state(&mut self, state: &SaveState) -> Result<(), SaveStateError> {
+        let ram_size = self.bus.ram().size() as usize;
+        if state.ram.len() != ram_size {
+            return Err(SaveStateError::RamSizeMismatch {
+                saved: state.ram.len(),
+                expected: ram_size,
+            });
+        }
+        let device_count = self.bus.devices().count();
+        if state.devices.len() != device_count {
+            return Err(SaveStateError::DeviceMismatch(
+                state.devices.len().min(device_count),
+            ));
+        }
+        for (i, ((base, device), saved)) in self.bus.devices().zip(&state.devices).enumerate() {
+            if base != saved.base || device.name() != saved.name {
+                return Err(SaveStateError::DeviceMismatch(i));
+            }
+        }
+        let rom = Rom::from_data(state.rom.clone())?.with_entry_point(state.entry_point);
+
+        // Put back the devices already loaded if one rejects its state
+        let backups: Vec<_> = self
+            .bus
+            .devices()
+            .map(|(_, device)| device.save_state())
+            .collect();
+        let rejected = self
+            .bus
+            .devices_mut()
+            .zip(&state.devices)
+            .position(|((_, device), saved)| device.load_state(&saved.data).is_none());
+        if let Some(i) = rejected {
+            for ((_, device), backup) in self.bus.devices_mut().zip(&backups).take(i) {
+                device.load_state(backup);
+            }
+            return Err(SaveStateError::DeviceState(i));
+        }
+
+        *self.bus.rom_mut() = rom;
+        self.bus.ram_mut().data_mut().copy_from_slice(&state.ram);
+
+        self.last_alu = state.alu;
+        self.pc = state.pc;
+        self.sp = state.sp;
+        let [a, b, c, d] = state.registers;
+        self.reg_a = a;
+        self.reg_b = b;
+        self.reg_c = c;
+        self.reg_d = d;
+
+        self.interrupts.set_enabled(state.interrupts_enabled);
+        self.interrupts.set_mask(state.interrupt_mask);
+        self.interrupts.set_pending(state.interrupts_pending);
+        self.state = match state.state {
+            MachineState::Faulted => MachineState::Running,
+            machine_state => machine_state,
+        };
+        self.fault = state.fault;
+        self.at_breakpoint = false;
+        self.watch_hit = None;
+
+        self.cycles = state.cycles;
+        self.instructions_retired = state.instructions_retired;
 
-            rom: Rom::new(0, rom_size),
-            ram: Ram::new(0, ram_size),
+        Ok(())
+    }
+
+    // The cycles the next tick will take if it succeeds, which is none if it
+    // can't even fetch its opcode
+    fn next_tick_cycles(&self) -> u32 {
+        if self.state == MachineState::Waiting && !self.interrupts.raised() {
+            return WAIT_CYCLES;
         }
+        if self.interrupts.next().is_some() {
+            return INTERRUPT_CYCLES;
+        }
+        self.bus
+            .peek(self.pc)
+            .ok()
+            .and_then(|byte| Opcode::try_from(byte).ok())
+            .map_or(0, Opcode::cycles)
+    }
+
+    // Read a byte from the bus, noting whether it matches a watchpoint
+    fn read8(&mut self, address: u16) -> Result<u8, BusError> {
+        let value = self.bus.read(address)?;
+        self.watch(address, Access::Read, value);
+        Ok(value)
+    }
+
+    // Write a byte onto the bus, noting whether it matches a watchpoint
+    fn write8(&mut self, address: u16, value: u8) -> Result<(), BusError> {
+        self.bus.write(address, value)?;
+        self.watch(address, Access::Write, value);
+        Ok(())
+    }
+
+    fn watch(&mut self, address: u16, access: Access, value: u8) {
+        if self.watch_hit.is_none() && self.breakpoints.watching(address, access, value) {
+            self.watch_hit = Some((address, access, value));
+        }
+    }
+
+    // Read a little-endian word from the bus at `address`
+    fn load16(&mut self, address: u16) -> Result<u16, BusError> {
+        let low = self.read8(address)?;
+        let high = self.read8(address.wrapping_add(1))?;
+        Ok(u16::from_le_bytes([low, high]))
+    }
+
+    // Write a word onto the bus at `address` in little-endian order
+    fn store16(&mut self, address: u16, value: u16) -> Result<(), BusError> {
+        let [low, high] = value.to_le_bytes();
+        self.write8(address, low)?;
+        self.write8(address.wrapping_add(1), high)
+    }
+
+    // The number of bytes on the stack
+    fn stack_used(&self) -> u32 {
+        self.stack_top.wrapping_sub(self.sp) as u32
+    }
+
+    // Push a word onto the stack, failing if there is no more room in RAM
+    fn push16(&mut self, value: u16) -> Result<(), Fault> {
+        if self.stack_used() + 2 > self.stack_size {
+            return Err(Fault::StackOverflow);
+        }
+        let sp = self.sp.wrapping_sub(2);
+        self.store16(sp, value)?;
+        self.sp = sp;
+        Ok(())
+    }
+
+    // Pop a word off of the stack, failing if the stack is empty
+    fn pop16(&mut self) -> Result<u16, Fault> {
+        if self.stack_used() < 2 {
+            return Err(Fault::StackUnderflow);
+        }
+        let value = self.load16(self.sp)?;
+        self.sp = self.sp.wrapping_add(2);
+        Ok(value)
+    }
+
+    // Take the highest priority interrupt if there is one, returning whether
+    // it was taken
+    fn take_interrupt(&mut self) -> Result<bool, Fault> {
+        let line = match self.interrupts.next() {
+            Some(line) => line,
+            None => return Ok(false),
+        };
+
+        // Save the state to return to, then jump to the handler. If that
+        // fails the stack is put back and the line is left pending
+        let handler = self.load16(InterruptController::vector(line))?;
+        let sp = self.sp;
+        let flags = self.last_alu.flags();
+        if let Err(fault) = self.push16(self.pc).and_then(|()| self.push16(flags)) {
+            self.sp = sp;
+            return Err(fault);
+        }
+        self.interrupts.acknowledge(line);
+        self.interrupts.set_enabled(false);
+        self.pc = handler;
+
+        Ok(true)
+    }
+
+    // Describe a fault raised by the instruction at `pc`
+    fn tick_error(&self, pc: u16, fault: Fault) -> TickError {
+        match fault {
+            Fault::Decode(DecodeError::IllegalOpcode(opcode)) => TickError::IllegalOpcode {
+                pc,
+                address: pc,
+                opcode,
+            },
+            Fault::Decode(DecodeError::IllegalRegister(register)) => TickError::IllegalRegister {
+                pc,
+                address: pc.wrapping_add(1),
+                register,
+            },
+            Fault::Decode(DecodeError::OutOfBounds(address))
+            | Fault::Bus(BusError::Unmapped(address)) => TickError::OutOfBounds { pc, address },
+            Fault::Bus(error) => {
+                let address = error.address();
+                match self.bus.region(address).map(|region| &region.target) {
+                    Some(Target::Rom) => TickError::RomWrite { pc, address },
+                    _ => TickError::Device { pc, address, error },
+                }
+            }
+            Fault::StackOverflow => TickError::StackOverflow {
+                pc,
+                address: self.sp.wrapping_sub(2),
+            },
+            Fault::StackUnderflow => TickError::StackUnderflow {
+                pc,
+                address: self.sp,
+            },
+            Fault::DivideByZero => TickError::DivideByZero { pc, address: pc },
+            Fault::Breakpoint => TickError::Breakpoint { pc, address: pc },
+        }
+    }
+
+    // Take an interrupt or execute an instruction, returning the cycles it
+    // took and leaving the program counter wherever it got to even on failure.
+    // A breakpoint at the instruction is ignored when `resuming` from it.
+    fn execute(&mut self, resuming: bool) -> Result<u32, Fault> {
+        // A waiting machine idles until an unmasked interrupt is raised
+        if self.state == MachineState::Waiting {
+            let line = match self.interrupts.highest_raised() {
+                Some(line) => line,
+                None => return Ok(WAIT_CYCLES),
+            };
+            self.state = MachineState::Running;
+            // With interrupts disabled nothing takes the line that woke the
+            // machine, so clear it here for the next wait to wait again
+            if !self.interrupts.enabled() {
+                self.interrupts.acknowledge(line);
+            }
+        }
+
+        // Entering an interrupt handler takes the place of an instruction
+        if self.take_interrupt()? {
+            return Ok(INTERRUPT_CYCLES);
+        }
+
+        if !resuming && self.breakpoints.breakpoint_at(self.pc) {
+            return Err(Fault::Breakpoint);
+        }
+
+        // Fetch and decode the instruction, then move past it
+        let instruction = Instruction::decode(&self.bus, self.pc)?;
+        self.pc = self.pc.wrapping_add(instruction.size());
+
+        // The operand and copies of the registers and flags for the ALU
+        // operations and conditional jumps
+        let operand = instruction.operand().value();
+        let (a, b) = (self.reg_a, self.reg_b);
+        let flags = self.last_alu;
+        let address = self.effective_address(instruction.opcode().address_mode(), operand, b);
+
+        // Execute the instruction
+        match instruction.opcode() {
+            Opcode::NoOp => {}
+
+            Opcode::LdA16 | Opcode::LdA8 => self.reg_a = operand,
+            Opcode::LdB16 | Opcode::LdB8 => self.reg_b = operand,
+            Opcode::StA16 | Opcode::StA8 => self.store16(address, a)?,
+            Opcode::StB16 | Opcode::StB8 => self.store16(address, b)?,
+
+            // ALU results are stored in the `A` register unless the operation
+            // only acts on the `B` register
+            Opcode::Add => self.reg_a = self.alu_op(|alu| alu.add16(a, b)),
+            Opcode::Sub => self.reg_a = self.alu_op(|alu| alu.sub16(a, b)),
+            Opcode::NegA => self.reg_a = self.alu_op(|alu| alu.neg16(a)),
+            Opcode::NegB => self.reg_b = self.alu_op(|alu| alu.neg16(b)),
+            Opcode::IncA => self.reg_a = self.alu_op(|alu| alu.inc16(a)),
+            Opcode::IncB => self.reg_b = self.alu_op(|alu| alu.inc16(b)),
+
+            Opcode::PassA => self.reg_a = self.alu_op(|alu| alu.pass16(a)),
+            Opcode::PassB => self.reg_b = self.alu_op(|alu| alu.pass16(b)),
+
+            Opcode::And => self.reg_a = self.alu_op(|alu| alu.and16(a, b)),
+            Opcode::Or => self.reg_a = self.alu_op(|alu| alu.or16(a, b)),
+            Opcode::XOr => self.reg_a = self.alu_op(|alu| alu.xor16(a, b)),
+            Opcode::BitFlpA => self.reg_a = self.alu_op(|alu| alu.complement(a)),
+            Opcode::BitFlpB => self.reg_b = self.alu_op(|alu| alu.complement(b)),
+
+            Opcode::ShftL => self.reg_a = self.alu_op(|alu| alu.shift16l(a, b)),
+            Opcode::ShftR => self.reg_a = self.alu_op(|alu| alu.shift16r(a, b)),
+            Opcode::UShftL => self.reg_a = self.alu_op(|alu| alu.ushift16l(a, b)),
+            Opcode::UShftR => self.reg_a = self.alu_op(|alu| alu.ushift16r(a, b)),
+            Opcode::RotL => self.reg_a = self.alu_op(|alu| alu.rot16l(a, b)),
+            Opcode::RotR => self.reg_a = self.alu_op(|alu| alu.rot16r(a, b)),
+
+            Opcode::Jmp => self.pc = operand,
+            Opcode::JmpRel => self.pc = self.pc.wrapping_add(operand),
+            Opcode::JmpZ => self.jump_if(flags.zero, operand),
+            Opcode::JmpNZ => self.jump_if(!flags.zero, operand),
+            Opcode::JmpC => self.jump_if(flags.carry_out, operand),
+            Opcode::JmpNC => self.jump_if(!flags.carry_out, operand),
+            Opcode::JmpN => self.jump_if(flags.negative, operand),
+            Opcode::JmpNN => self.jump_if(!flags.negative, operand),
+            Opcode::JmpO => self.jump_if(flags.overflow, operand),
+            Opcode::JmpNO => self.jump_if(!flags.overflow, operand),
+            Opcode::JmpP => self.jump_if(flags.parity, operand),
+            Opcode::JmpNP => self.jump_if(!flags.parity, operand),
+
+            Opcode::Cmp => {
+                self.alu_op(|alu| alu.sub16(a, b));
+            }
+
+            Opcode::PushA => self.push16(a)?,
+            Opcode::PushB => self.push16(b)?,
+            Opcode::PushF => self.push16(flags.flags())?,
+            Opcode::PopA => self.reg_a = self.pop16()?,
+            Opcode::PopB => self.reg_b = self.pop16()?,
+            Opcode::PopF => {
+                let popped = self.pop16()?;
+                self.last_alu.set_flags(popped);
+            }
+            Opcode::Call => {
+                self.push16(self.pc)?;
+                self.pc = operand;
+            }
+            Opcode::Ret => self.pc = self.pop16()?,
+
+            Opcode::Ei => self.interrupts.set_enabled(true),
+            Opcode::Di => self.interrupts.set_enabled(false),
+            Opcode::RetI => {
+                let popped = self.pop16()?;
+                self.last_alu.set_flags(popped);
+                self.pc = self.pop16()?;
+                self.interrupts.set_enabled(true);
+            }
+            Opcode::SetIM => self.interrupts.set_mask(operand as u8),
+
+            // Chain the carry or borrow out of the last operation into this one
+            Opcode::Adc => self.reg_a = self.alu_op(|alu| alu.add16_carry(a, b, flags.carry_out)),
+            Opcode::Sbc => self.reg_a = self.alu_op(|alu| alu.sub16_borrow(a, b, flags.carry_out)),
+            Opcode::RotLC => {
+                self.reg_a = self.alu_op(|alu| alu.rot16l_carry(a, b, flags.carry_out))
+            }
+            Opcode::RotRC => {
+                self.reg_a = self.alu_op(|alu| alu.rot16r_carry(a, b, flags.carry_out))
+            }
+
+            // Multiplication keeps the high word of the product in the `B`
+            // register
+            Opcode::Mul => (self.reg_a, self.reg_b) = self.alu_wide_op(|alu| alu.mul16(a, b)),
+            Opcode::IMul => (self.reg_a, self.reg_b) = self.alu_wide_op(|alu| alu.imul16(a, b)),
+            Opcode::Div => self.reg_a = self.alu_div_op(|alu| alu.div16(a, b))?,
+            Opcode::IDiv => self.reg_a = self.alu_div_op(|alu| alu.idiv16(a, b))?,
+            Opcode::Mod => self.reg_a = self.alu_div_op(|alu| alu.mod16(a, b))?,
+            Opcode::IMod => self.reg_a = self.alu_div_op(|alu| alu.imod16(a, b))?,
+
+            Opcode::LdAI | Opcode::LdAX => self.reg_a = self.load16(address)?,
+            Opcode::StAI | Opcode::StAX => self.store16(address, a)?,
+            // The pointer only moves on once the access has succeeded
+            Opcode::LdAIP => {
+                self.reg_a = self.load16(address)?;
+                self.reg_b = b.wrapping_add(2);
+            }
+            Opcode::StAIP => {
+                self.store16(address, a)?;
+                self.reg_b = b.wrapping_add(2);
+            }
+
+            Opcode::Mov | Opcode::Swap => {
+                if let Operand::Registers(first, second) = instruction.operand() {
+                    let value = self.register(second);
+                    if instruction.opcode() == Opcode::Swap {
+                        self.set_register(second, self.register(first));
+                    }
+                    self.set_register(first, value);
+                }
+            }
+
+            Opcode::Halt => self.state = MachineState::Halted { exit_code: a },
+            Opcode::Wait => self.state = MachineState::Waiting,
+        }
+
+        self.instructions_retired += 1;
+        Ok(instruction.opcode().cycles())
+    }
+
+    // The address a load or store accesses, formed from its operand and the
+    // `B` register
+    fn effective_address(&self, mode: AddressMode, operand: u16, b: u16) -> u16 {
+        match mode {
+            AddressMode::None | AddressMode::Direct => operand,
+            AddressMode::ZeroPage => self.zero_page.wrapping_add(operand),
+            AddressMode::Indirect | AddressMode::PostIncrement => b,
+            AddressMode::Indexed => operand.wrapping_add(b),
+        }
+    }
+
+    // Move the program counter to `address` if the condition holds
+    fn jump_if(&mut self, condition: bool, address: u16) {
+        if condition {
+            self.pc = address;
+        }
+    }
+
+    // Perform an ALU operation, keeping its outputs and returning the value
+    fn alu_op(&mut self, op: impl FnOnce(&mut CJEmuAlu) -> AluOutputs) -> u16 {
+        self.last_alu = op(&mut self.alu);
+        self.last_alu.value
+    }
+
+    // Perform an ALU operation with a second result word, keeping its outputs
+    // and returning the value and the second word
+    fn alu_wide_op(&mut self, op: impl FnOnce(&mut CJEmuAlu) -> (AluOutputs, u16)) -> (u16, u16) {
+        let (outputs, high) = op(&mut self.alu);
+        self.last_alu = outputs;
+        (outputs.value, high)
+    }
+
+    // Perform an ALU division, which fails when dividing by zero and leaves
+    // the outputs of the last operation alone
+    fn alu_div_op(
+        &mut self,
+        op: impl FnOnce(&mut CJEmuAlu) -> Option<AluOutputs>,
+    ) -> Result<u16, Fault> {
+        self.last_alu = op(&mut self.alu).ok_or(Fault::DivideByZero)?;
+        Ok(self.last_alu.value)
     }
 }
 
 impl VirtualMachine<Rom, Ram> for CJEmuVirtualMachine {
-    type TickErrorTy = ();
+    type TickErrorTy = TickError;
 
     fn last_alu(&self) -> AluOutputs {
         self.last_alu
     }
 
-    fn reg_a(&self) -> u16 {
-        self.reg_a
+    fn state(&self) -> MachineState {
+        match self.fault {
+            Some(_) => MachineState::Faulted,
+            None => self.state,
+        }
+    }
+
+    fn pc(&self) -> u16 {
+        self.pc
     }
 
-    fn reg_b(&self) -> u16 {
-        self.reg_b
+    fn sp(&self) -> u16 {
+        self.sp
+    }
+
+    fn register(&self, register: Register) -> u16 {
+        match register {
+            Register::A => self.reg_a,
+            Register::B => self.reg_b,
+            Register::C => self.reg_c,
+            Register::D => self.reg_d,
+            Register::Flags => self.last_alu.flags(),
+        }
     }
 
     fn rom(&self) -> &Rom {
-        &self.rom
+        self.bus.rom()
     }
 
     fn ram(&self) -> &Ram {
-        &self.ram
+        self.bus.ram()
+    }
+
+    fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    fn instructions_retired(&self) -> u64 {
+        self.instructions_retired
+    }
+
+    fn raise_interrupt(&mut self, line: u8) -> Option<()> {
+        self.interrupts.raise(line)
     }
 
     fn perform_tick(&mut self) -> Result<(), Self::TickErrorTy> {
-        Ok(())
+        if self.fault.is_some() || matches!(self.state, MachineState::Halted { .. }) {
+            return Err(TickError::Halted {
+                pc: self.pc,
+                address: self.pc,
+            });
+        }
+
+        // On failure, stop with the program counter at the instruction that
+        // failed, staying stopped unless it only paused at a breakpoint
+        let pc = self.pc;
+        let resuming = std::mem::take(&mut self.at_breakpoint);
+        self.watch_hit = None;
+        let cycles = self.execute(resuming).map_err(|fault| {
+            self.pc = pc;
+            let error = self.tick_error(pc, fault);
+            if error.is_fault() {
+                self.fault = Some(error);
+            } else {
+                self.at_breakpoint = true;
+            }
+            error
+        })?;
+        self.cycles += cycles as u64;
+
+        // Let the devices catch up, raising any interrupts they request to be
+        // taken before the next instruction
+        let interrupts = &mut self.interrupts;
+        self.bus.step_devices(cycles, |line| {
+            interrupts.raise(line);
+        });
+
+        // Watchpoints pause once the instruction that hit them has finished
+        match self.watch_hit {
+            Some((address, access, value)) => Err(TickError::Watchpoint {
+                pc,
+                address,
+                access,
+                value,
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+impl DebugVirtualMachine<Rom, Ram> for CJEmuVirtualMachine {
+    fn set_register(&mut self, register: Register, value: u16) {
+        match register {
+            Register::A => self.reg_a = value,
+            Register::B => self.reg_b = value,
+            Register::C => self.reg_c = value,
+            Register::D => self.reg_d = value,
+            Register::Flags => self.last_alu.set_flags(value),
+        }
+    }
+
+    fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+        // A breakpoint at the new address should stop execution there
+        self.at_breakpoint = false;
+    }
+
+    fn ram_mut(&mut self) -> &mut Ram {
+        self.bus.ram_mut()
+    }
+
+    fn patch_rom(&mut self, address: u16, value: u8) -> Option<()> {
+        self.bus.rom_mut().patch(address, value)
+    }
+
+    fn reset(&mut self) {
+        self.last_alu = AluOutputs::default();
+
+        self.pc = self.bus.rom().entry_point();
+        self.sp = self.stack_top;
+        self.reg_a = 0;
+        self.reg_b = 0;
+        self.reg_c = 0;
+        self.reg_d = 0;
+
+        self.interrupts = InterruptController::new();
+        self.state = MachineState::Running;
+        self.fault = None;
+        self.at_breakpoint = false;
+        self.watch_hit = None;
+
+        self.cycles = 0;
+        self.instructions_retired = 0;
+
+        self.bus.reset_devices();
     }
 }
@@ -0,0 +1,351 @@
+//! Compares `CJEmuAlu` against a bit-by-bit reference model of each operation.
+
+use cjemu_runtime::cjemu_api::{Alu, AluOutputs};
+use cjemu_runtime::CJEmuAlu;
+
+// Right hand operands checked against every possible left hand operand
+fn operands() -> Vec<u16> {
+    let mut values = vec![0, 1, 2, 0x7FFE, 0x7FFF, 0x8000, 0x8001, 0xFFFE, 0xFFFF];
+    values.extend((0..=u16::MAX).step_by(0x1FF7));
+    values
+}
+
+// Shift and rotate amounts, including those past the width of a value
+fn amounts() -> Vec<u16> {
+    (0..=34).collect()
+}
+
+fn bit(value: u16, index: u32) -> bool {
+    value >> index & 1 != 0
+}
+
+fn reference(value: u16, carry_out: bool, overflow: bool) -> AluOutputs {
+    AluOutputs {
+        value,
+        carry_out,
+        zero: value == 0,
+        negative: bit(value, 15),
+        overflow,
+        parity: (0..16).filter(|&i| bit(value, i)).count() & 1 == 0,
+    }
+}
+
+// A ripple carry adder, with overflow when the carry into the sign bit
+// differs from the carry out of it
+fn ref_add(a: u16, b: u16, carry: bool) -> AluOutputs {
+    let mut value = 0;
+    let mut carry = carry;
+    let mut carry_into_sign = false;
+    for i in 0..16 {
+        if i == 15 {
+            carry_into_sign = carry;
+        }
+        let (x, y) = (bit(a, i), bit(b, i));
+        value |= ((x ^ y ^ carry) as u16) << i;
+        carry = (x && y) || (carry && (x ^ y));
+    }
+    reference(value, carry, carry ^ carry_into_sign)
+}
+
+// Subtraction as the addition of the complement, where the borrow is the
+// inverse of the carry
+fn ref_sub(a: u16, b: u16, borrow: bool) -> AluOutputs {
+    let outputs = ref_add(a, !b, !borrow);
+    reference(outputs.value, !outputs.carry_out, outputs.overflow)
+}
+
+fn ref_shift_l(a: u16, b: u16) -> AluOutputs {
+    let mut value = a;
+    let mut carry = false;
+    for _ in 0..b {
+        carry = bit(value, 14);
+        value = (value & 0x8000) | ((value << 1) & 0x7FFF);
+    }
+    let expected = (a as i16 as i64) * 2i64.pow(b as u32);
+    reference(value, carry, expected != value as i16 as i64)
+}
+
+fn ref_shift_r(a: u16, b: u16) -> AluOutputs {
+    let mut value = a;
+    let mut carry = false;
+    for _ in 0..b {
+        carry = bit(value, 0);
+        value = (value & 0x8000) | (value >> 1);
+    }
+    reference(value, carry, false)
+}
+
+fn ref_ushift_l(a: u16, b: u16) -> AluOutputs {
+    let mut value = a;
+    let mut carry = false;
+    for _ in 0..b {
+        carry = bit(value, 15);
+        value <<= 1;
+    }
+    reference(value, carry, false)
+}
+
+fn ref_ushift_r(a: u16, b: u16) -> AluOutputs {
+    let mut value = a;
+    let mut carry = false;
+    for _ in 0..b {
+        carry = bit(value, 0);
+        value >>= 1;
+    }
+    reference(value, carry, false)
+}
+
+fn ref_rot_l(a: u16, b: u16, carry_in: Option<bool>) -> AluOutputs {
+    let mut value = a;
+    let mut carry = carry_in.unwrap_or(false);
+    for _ in 0..b {
+        let out = bit(value, 15);
+        value = value << 1 | carry_in.map_or(out, |_| carry) as u16;
+        carry = out;
+    }
+    if carry_in.is_none() {
+        carry = b != 0 && bit(value, 0);
+    }
+    reference(value, carry, false)
+}
+
+fn ref_rot_r(a: u16, b: u16, carry_in: Option<bool>) -> AluOutputs {
+    let mut value = a;
+    let mut carry = carry_in.unwrap_or(false);
+    for _ in 0..b {
+        let out = bit(value, 0);
+        value = value >> 1 | (carry_in.map_or(out, |_| carry) as u16) << 15;
+        carry = out;
+    }
+    if carry_in.is_none() {
+        carry = b != 0 && bit(value, 15);
+    }
+    reference(value, carry, false)
+}
+
+// Long multiplication, adding `a` shifted left once for each set bit of `b`
+fn ref_mul(a: u16, b: u16) -> u32 {
+    (0..16)
+        .filter(|&i| bit(b, i))
+        .fold(0u32, |product, i| product.wrapping_add((a as u32) << i))
+}
+
+// Signed multiplication of the magnitudes, negated when the signs differ
+fn ref_imul(a: u16, b: u16) -> u32 {
+    let magnitude = ref_mul((a as i16).unsigned_abs(), (b as i16).unsigned_abs());
+    if bit(a, 15) != bit(b, 15) {
+        magnitude.wrapping_neg()
+    } else {
+        magnitude
+    }
+}
+
+fn ref_mul_outputs(product: u32, significant: bool) -> (AluOutputs, u16) {
+    (
+        reference(product as u16, significant, significant),
+        (product >> 16) as u16,
+    )
+}
+
+// Restoring long division, bringing down a bit of `a` at a time, returning the
+// quotient and remainder
+fn ref_divmod(a: u16, b: u16) -> (u16, u16) {
+    let mut quotient = 0;
+    let mut remainder = 0u32;
+    for i in (0..16).rev() {
+        remainder = remainder << 1 | bit(a, i) as u32;
+        if remainder >= b as u32 {
+            remainder -= b as u32;
+            quotient |= 1 << i;
+        }
+    }
+    (quotient, remainder as u16)
+}
+
+// Signed division of the magnitudes, where the quotient is negative when the
+// signs differ and the remainder takes the sign of `a`
+fn ref_idivmod(a: u16, b: u16) -> (u16, u16) {
+    let (quotient, remainder) = ref_divmod((a as i16).unsigned_abs(), (b as i16).unsigned_abs());
+    let negate = |value: u16, negative: bool| {
+        if negative {
+            value.wrapping_neg()
+        } else {
+            value
+        }
+    };
+    (
+        negate(quotient, bit(a, 15) != bit(b, 15)),
+        negate(remainder, bit(a, 15)),
+    )
+}
+
+#[test]
+fn add() {
+    let mut alu = CJEmuAlu {};
+    for b in operands() {
+        for a in 0..=u16::MAX {
+            assert_eq!(alu.add16(a, b), ref_add(a, b, false), "{} + {}", a, b);
+            assert_eq!(
+                alu.add16_carry(a, b, true),
+                ref_add(a, b, true),
+                "{} + {} + 1",
+                a,
+                b
+            );
+        }
+    }
+}
+
+#[test]
+fn sub() {
+    let mut alu = CJEmuAlu {};
+    for b in operands() {
+        for a in 0..=u16::MAX {
+            assert_eq!(alu.sub16(a, b), ref_sub(a, b, false), "{} - {}", a, b);
+            assert_eq!(
+                alu.sub16_borrow(a, b, true),
+                ref_sub(a, b, true),
+                "{} - {} - 1",
+                a,
+                b
+            );
+        }
+    }
+}
+
+#[test]
+fn unary() {
+    let mut alu = CJEmuAlu {};
+    for a in 0..=u16::MAX {
+        assert_eq!(alu.neg16(a), ref_sub(0, a, false), "-{}", a);
+        assert_eq!(alu.inc16(a), ref_add(a, 1, false), "{} + 1", a);
+        assert_eq!(alu.pass16(a), reference(a, false, false), "{}", a);
+        assert_eq!(alu.complement(a), reference(!a, false, false), "!{}", a);
+    }
+}
+
+#[test]
+fn bit_logic() {
+    let mut alu = CJEmuAlu {};
+    for b in operands() {
+        for a in 0..=u16::MAX {
+            assert_eq!(alu.and16(a, b), reference(a & b, false, false));
+            assert_eq!(alu.or16(a, b), reference(a | b, false, false));
+            assert_eq!(alu.xor16(a, b), reference(a ^ b, false, false));
+        }
+    }
+}
+
+#[test]
+fn shifts() {
+    let mut alu = CJEmuAlu {};
+    for b in amounts() {
+        for a in 0..=u16::MAX {
+            assert_eq!(alu.shift16l(a, b), ref_shift_l(a, b), "{} << {}", a, b);
+            assert_eq!(alu.shift16r(a, b), ref_shift_r(a, b), "{} >> {}", a, b);
+            assert_eq!(alu.ushift16l(a, b), ref_ushift_l(a, b), "{} <<< {}", a, b);
+            assert_eq!(alu.ushift16r(a, b), ref_ushift_r(a, b), "{} >>> {}", a, b);
+        }
+    }
+}
+
+#[test]
+fn rotates() {
+    let mut alu = CJEmuAlu {};
+    for b in amounts() {
+        for a in 0..=u16::MAX {
+            assert_eq!(alu.rot16l(a, b), ref_rot_l(a, b, None), "{} rotl {}", a, b);
+            assert_eq!(alu.rot16r(a, b), ref_rot_r(a, b, None), "{} rotr {}", a, b);
+            for &carry in &[false, true] {
+                assert_eq!(
+                    alu.rot16l_carry(a, b, carry),
+                    ref_rot_l(a, b, Some(carry)),
+                    "{} rotl {} with carry {}",
+                    a,
+                    b,
+                    carry
+                );
+                assert_eq!(
+                    alu.rot16r_carry(a, b, carry),
+                    ref_rot_r(a, b, Some(carry)),
+                    "{} rotr {} with carry {}",
+                    a,
+                    b,
+                    carry
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn multiply() {
+    let mut alu = CJEmuAlu {};
+    for b in operands() {
+        for a in 0..=u16::MAX {
+            let product = ref_mul(a, b);
+            assert_eq!(
+                alu.mul16(a, b),
+                ref_mul_outputs(product, product > 0xFFFF),
+                "{} * {}",
+                a,
+                b
+            );
+
+            // Signed products that fit in a word have a high word made of
+            // copies of the sign bit of the low word
+            let product = ref_imul(a, b);
+            let sign = if bit(product as u16, 15) { 0xFFFF } else { 0 };
+            assert_eq!(
+                alu.imul16(a, b),
+                ref_mul_outputs(product, (product >> 16) as u16 != sign),
+                "{} * {} signed",
+                a as i16,
+                b as i16
+            );
+        }
+    }
+}
+
+#[test]
+fn divide() {
+    let mut alu = CJEmuAlu {};
+    for b in operands().into_iter().filter(|&b| b != 0) {
+        for a in 0..=u16::MAX {
+            let (quotient, remainder) = ref_divmod(a, b);
+            let outputs = |value| Some(reference(value, false, false));
+            assert_eq!(alu.div16(a, b), outputs(quotient), "{} / {}", a, b);
+            assert_eq!(alu.mod16(a, b), outputs(remainder), "{} % {}", a, b);
+
+            // Only the most negative value divided by -1 overflows, where the
+            // quotient wraps back around to the most negative value
+            let (quotient, remainder) = ref_idivmod(a, b);
+            let overflow = a == 0x8000 && b == 0xFFFF;
+            assert_eq!(
+                alu.idiv16(a, b),
+                Some(reference(quotient, false, overflow)),
+                "{} / {} signed",
+                a as i16,
+                b as i16
+            );
+            assert_eq!(
+                alu.imod16(a, b),
+                outputs(remainder),
+                "{} % {} signed",
+                a as i16,
+                b as i16
+            );
+        }
+    }
+}
+
+#[test]
+fn divide_by_zero() {
+    let mut alu = CJEmuAlu {};
+    for a in 0..=u16::MAX {
+        assert_eq!(alu.div16(a, 0), None, "{} / 0", a);
+        assert_eq!(alu.idiv16(a, 0), None, "{} / 0 signed", a as i16);
+        assert_eq!(alu.mod16(a, 0), None, "{} % 0", a);
+        assert_eq!(alu.imod16(a, 0), None, "{} % 0 signed", a as i16);
+    }
+}
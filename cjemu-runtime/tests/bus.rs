@@ -0,0 +1,135 @@
+//! Checks how bus layouts map the address space onto ROM, RAM and devices.
+
+use cjemu_runtime::cjemu_api::{BusError, WritableMemory};
+use cjemu_runtime::{
+    BusLayout, LayoutError, MemoryBus, Ram, Region, Rom, Target, Timer, UnmappedAccess,
+};
+
+fn machine_bus(layout: BusLayout) -> MemoryBus {
+    let rom = Rom::from_data(vec![0x10, 0x11, 0x12, 0x13]).unwrap();
+    let mut ram = Ram::new(0, 0x100).unwrap();
+    ram.set_byte(0x00, 0x20).unwrap();
+    ram.set_byte(0xFF, 0x2F).unwrap();
+    MemoryBus::new(rom, ram, layout).unwrap()
+}
+
+fn layout(regions: Vec<Region>, unmapped: UnmappedAccess) -> BusLayout {
+    BusLayout { regions, unmapped }
+}
+
+fn region_name(bus: &MemoryBus, address: u16) -> Option<&str> {
+    bus.region(address).map(|region| region.name.as_str())
+}
+
+#[test]
+fn mirrored_rom() {
+    let mut bus = machine_bus(layout(
+        vec![Region::new("rom", 0x0000, 0x1000, Target::Rom).with_mirroring()],
+        UnmappedAccess::Fault,
+    ));
+    assert_eq!(bus.read(0x0001), Ok(0x11));
+    assert_eq!(bus.read(0x0005), Ok(0x11));
+    assert_eq!(bus.read(0x0FFF), Ok(0x13));
+    assert_eq!(bus.peek(0x0FFE), Ok(0x12));
+    // Mirrors are still read-only
+    assert_eq!(bus.write(0x0004, 0), Err(BusError::ReadOnly(0x0004)));
+    assert_eq!(bus.read(0x1000), Err(BusError::Unmapped(0x1000)));
+
+    // Without mirroring the rest of the region is unmapped
+    let mut bus = machine_bus(layout(
+        vec![Region::new("rom", 0x0000, 0x1000, Target::Rom)],
+        UnmappedAccess::Fault,
+    ));
+    assert_eq!(bus.read(0x0003), Ok(0x13));
+    assert_eq!(bus.read(0x0004), Err(BusError::Unmapped(0x0004)));
+}
+
+#[test]
+fn unmapped_access() {
+    let regions = || vec![Region::new("ram", 0x8000, 0x8000, Target::Ram)];
+
+    let mut bus = machine_bus(layout(regions(), UnmappedAccess::Fault));
+    assert_eq!(bus.read(0x0000), Err(BusError::Unmapped(0x0000)));
+    assert_eq!(bus.peek(0x0000), Err(BusError::Unmapped(0x0000)));
+    assert_eq!(bus.write(0x0000, 1), Err(BusError::Unmapped(0x0000)));
+    // Past the end of RAM within its region
+    assert_eq!(bus.read(0x8100), Err(BusError::Unmapped(0x8100)));
+    assert_eq!(bus.read(0x80FF), Ok(0x2F));
+
+    let mut bus = machine_bus(layout(regions(), UnmappedAccess::OpenBus(0xEE)));
+    assert_eq!(bus.read(0x0000), Ok(0xEE));
+    assert_eq!(bus.peek(0x8100), Ok(0xEE));
+    assert_eq!(bus.write(0x0000, 1), Ok(()));
+    assert_eq!(bus.read(0x0000), Ok(0xEE));
+    assert_eq!(bus.read(0x8000), Ok(0x20));
+}
+
+#[test]
+fn layout_validation() {
+    let new = |regions| {
+        MemoryBus::new(
+            Rom::new(0, 0x10).unwrap(),
+            Ram::new(0, 0x10).unwrap(),
+            layout(regions, UnmappedAccess::Fault),
+        )
+        .err()
+    };
+
+    assert_eq!(
+        new(vec![
+            Region::new("rom", 0x0000, 0x10, Target::Rom),
+            Region::new("nothing", 0x1000, 0, Target::Ram),
+        ]),
+        Some(LayoutError::EmptyRegion("nothing".to_owned()))
+    );
+    assert_eq!(
+        new(vec![Region::new("ram", 0xFFF0, 0x11, Target::Ram)]),
+        Some(LayoutError::OutOfRange("ram".to_owned()))
+    );
+    // Regions may end at the end of the address space, or cover all of it
+    assert_eq!(
+        new(vec![Region::new("ram", 0xFFF0, 0x10, Target::Ram)]),
+        None
+    );
+    assert_eq!(
+        new(vec![Region::new("rom", 0x0000, 0x10000, Target::Rom)]),
+        None
+    );
+    let timer = Box::new(Timer::new(None));
+    assert_eq!(
+        MemoryBus::new(
+            Rom::new(0, 0x10).unwrap(),
+            Ram::new(0, 0x10).unwrap(),
+            BusLayout::default().with_device(0xFFF8, timer),
+        )
+        .err(),
+        Some(LayoutError::OutOfRange("timer".to_owned()))
+    );
+}
+
+#[test]
+fn later_regions_take_precedence() {
+    let mut bus = machine_bus(
+        layout(
+            vec![
+                Region::new("rom", 0x0000, 0x1000, Target::Rom).with_mirroring(),
+                Region::new("ram", 0x0800, 0x0100, Target::Ram),
+            ],
+            UnmappedAccess::Fault,
+        )
+        .with_device(0x0800, Box::new(Timer::new(None))),
+    );
+
+    assert_eq!(region_name(&bus, 0x0000), Some("rom"));
+    assert_eq!(region_name(&bus, 0x0800), Some("timer"));
+    assert_eq!(region_name(&bus, 0x0800 + Timer::SIZE), Some("ram"));
+    assert_eq!(region_name(&bus, 0x0900), Some("rom"));
+
+    // The timer covers the start of RAM, which covers part of the ROM mirrors
+    assert_eq!(bus.write(0x0800 + Timer::RELOAD, 0x34), Ok(()));
+    assert_eq!(bus.read(0x0800 + Timer::RELOAD), Ok(0x34));
+    assert_eq!(bus.ram().data()[Timer::RELOAD as usize], 0);
+    assert_eq!(bus.write(0x08FF, 0x55), Ok(()));
+    assert_eq!(bus.read(0x08FF), Ok(0x55));
+    assert_eq!(bus.read(0x0901), Ok(0x11));
+}
@@ -0,0 +1,83 @@
+//! Checks what the console sends for the bytes written to its port.
+
+use cjemu_runtime::cjemu_api::{BusError, Device};
+use cjemu_runtime::{Console, ConsoleEvent};
+
+// The events sent for `bytes` written to the port one at a time
+fn events(bytes: &[u8]) -> Vec<ConsoleEvent> {
+    let (mut console, events) = Console::channel();
+    for &byte in bytes {
+        console.write(Console::PORT, byte).unwrap();
+    }
+    events.try_iter().collect()
+}
+
+#[test]
+fn print() {
+    assert_eq!(
+        events(b"hi!"),
+        vec![
+            ConsoleEvent::Print('h'),
+            ConsoleEvent::Print('i'),
+            ConsoleEvent::Print('!'),
+        ]
+    );
+    // Bytes past ASCII are Latin-1
+    assert_eq!(events(&[0xE9]), vec![ConsoleEvent::Print('é')]);
+}
+
+#[test]
+fn control_bytes() {
+    assert_eq!(
+        events(&[
+            b'a',
+            ConsoleEvent::NEWLINE,
+            ConsoleEvent::BACKSPACE,
+            ConsoleEvent::CLEAR,
+        ]),
+        vec![
+            ConsoleEvent::Print('a'),
+            ConsoleEvent::Newline,
+            ConsoleEvent::Backspace,
+            ConsoleEvent::Clear,
+        ]
+    );
+}
+
+#[test]
+fn unknown_control_bytes_ignored() {
+    // Carriage returns, tabs, escapes and deletes among them
+    let ignored: Vec<u8> = (0..0x20)
+        .chain(std::iter::once(0x7F))
+        .filter(|&byte| {
+            ![
+                ConsoleEvent::NEWLINE,
+                ConsoleEvent::BACKSPACE,
+                ConsoleEvent::CLEAR,
+            ]
+            .contains(&byte)
+        })
+        .collect();
+    assert_eq!(ignored.len(), 30);
+    assert_eq!(events(&ignored), vec![]);
+
+    let mut bytes = ignored;
+    bytes.push(b'x');
+    assert_eq!(events(&bytes), vec![ConsoleEvent::Print('x')]);
+}
+
+#[test]
+fn port() {
+    let (mut console, events) = Console::channel();
+    // The high byte of the port is ignored, and it can't be read
+    assert_eq!(console.write(Console::PORT + 1, b'a'), Ok(()));
+    assert_eq!(
+        console.read(Console::PORT),
+        Err(BusError::WriteOnly(Console::PORT))
+    );
+    assert_eq!(events.try_iter().count(), 0);
+
+    // Nobody watching the output isn't an error
+    drop(events);
+    assert_eq!(console.write(Console::PORT, b'a'), Ok(()));
+}
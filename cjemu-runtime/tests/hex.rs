@@ -0,0 +1,185 @@
+//! Checks the Intel HEX and S-record readers and writers against each other
+//! and against records built by hand.
+
+use cjemu_runtime::cjemu_api::{WritableMemory, ADDRESS_SPACE};
+use cjemu_runtime::{HexError, Ram, RecordError, RomImage};
+
+// An Intel HEX record from its bytes, with the two's complement checksum
+fn ihex(bytes: &[u8]) -> String {
+    let sum = bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+    record(":", bytes, sum.wrapping_neg())
+}
+
+// An S-record of `record_type` from its bytes after the byte count, with the
+// ones' complement checksum
+fn srec(record_type: &str, bytes: &[u8]) -> String {
+    let mut bytes = bytes.to_vec();
+    bytes.insert(0, bytes.len() as u8 + 1);
+    let sum = bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+    record(record_type, &bytes, !sum)
+}
+
+fn record(start_code: &str, bytes: &[u8], checksum: u8) -> String {
+    let digits: String = bytes
+        .iter()
+        .chain(&[checksum])
+        .map(|byte| format!("{:02X}", byte))
+        .collect();
+    format!("{}{}\n", start_code, digits)
+}
+
+// Segments at absolute addresses, apart enough not to be joined, including
+// one longer than a single record and one ending at the end of the address
+// space
+fn example() -> RomImage {
+    RomImage::new(0x0010, 0)
+        .with_segment(0x0000, vec![0x01, 0x02, 0x03])
+        .with_segment(0x0100, (0..40).collect())
+        .with_segment(0xFFFC, vec![0xAA, 0xBB, 0xCC, 0xDD])
+}
+
+#[test]
+fn intel_hex_round_trip() {
+    let image = example();
+    let text = image.to_intel_hex().unwrap();
+    assert_eq!(RomImage::from_intel_hex(&text), Ok(image));
+}
+
+#[test]
+fn srecord_round_trip() {
+    let image = example();
+    let text = image.to_srecord().unwrap();
+    assert_eq!(RomImage::from_srecord(&text), Ok(image));
+}
+
+#[test]
+fn intel_hex_checksum_mismatch() {
+    // Lines are counted from 1, including blank ones
+    let text = format!(
+        "{}\n{}{}",
+        ihex(&[2, 0x00, 0x00, 0x00, 0x12, 0x34]),
+        ":020002005678FF\n",
+        ihex(&[0, 0, 0, 1])
+    );
+    assert_eq!(
+        RomImage::from_intel_hex(&text),
+        Err(HexError::Record {
+            line: 3,
+            error: RecordError::ChecksumMismatch {
+                stored: 0xFF,
+                calculated: 0x2E,
+            },
+        })
+    );
+}
+
+#[test]
+fn srecord_checksum_mismatch() {
+    let text = format!(
+        "{}{}{}",
+        srec("S0", &[0, 0]),
+        "S1050000123400\n",
+        srec("S9", &[0, 0])
+    );
+    assert_eq!(
+        RomImage::from_srecord(&text),
+        Err(HexError::Record {
+            line: 2,
+            error: RecordError::ChecksumMismatch {
+                stored: 0x00,
+                calculated: 0xB4,
+            },
+        })
+    );
+}
+
+#[test]
+fn missing_end() {
+    let text = ihex(&[2, 0x00, 0x00, 0x00, 0x12, 0x34]);
+    assert_eq!(
+        RomImage::from_intel_hex(&text),
+        Err(HexError::Record {
+            line: 2,
+            error: RecordError::MissingEnd,
+        })
+    );
+
+    // A count record doesn't end an S-record file
+    let text = format!(
+        "{}{}{}",
+        srec("S0", &[0, 0]),
+        srec("S1", &[0x00, 0x00, 0x12, 0x34]),
+        srec("S5", &[0x00, 0x01])
+    );
+    assert_eq!(
+        RomImage::from_srecord(&text),
+        Err(HexError::Record {
+            line: 4,
+            error: RecordError::MissingEnd,
+        })
+    );
+    assert_eq!(
+        RomImage::from_srecord(&format!("{}{}", text, srec("S9", &[0, 0]))).map(|_| ()),
+        Ok(())
+    );
+}
+
+#[test]
+fn intel_hex_address_out_of_range() {
+    // Data at the end of a high extended linear address
+    let text = format!(
+        "{}{}{}",
+        ihex(&[2, 0x00, 0x00, 0x04, 0xFF, 0xFF]),
+        ihex(&[2, 0xFF, 0xFF, 0x00, 0x12, 0x34]),
+        ihex(&[0, 0, 0, 1])
+    );
+    assert_eq!(
+        RomImage::from_intel_hex(&text),
+        Err(HexError::Record {
+            line: 2,
+            error: RecordError::AddressOutOfRange(0xFFFF_FFFF),
+        })
+    );
+
+    // Data running past the end of the address space
+    let text = format!(
+        "{}{}",
+        ihex(&[2, 0xFF, 0xFF, 0x00, 0x12, 0x34]),
+        ihex(&[0, 0, 0, 1])
+    );
+    assert_eq!(
+        RomImage::from_intel_hex(&text),
+        Err(HexError::Record {
+            line: 1,
+            error: RecordError::AddressOutOfRange(0xFFFF),
+        })
+    );
+}
+
+#[test]
+fn srecord_address_out_of_range() {
+    let text = format!(
+        "{}{}",
+        srec("S3", &[0xFF, 0xFF, 0xFF, 0xFF, 0x12]),
+        srec("S7", &[0, 0, 0, 0])
+    );
+    assert_eq!(
+        RomImage::from_srecord(&text),
+        Err(HexError::Record {
+            line: 1,
+            error: RecordError::AddressOutOfRange(0xFFFF_FFFF),
+        })
+    );
+}
+
+#[test]
+fn from_memory_stops_at_end_of_address_space() {
+    let mut ram = Ram::new(0, ADDRESS_SPACE).unwrap();
+    ram.set_byte(0xFFFF, 0x42).unwrap();
+    ram.set_byte(0x0000, 0x24).unwrap();
+
+    let image = RomImage::from_memory(&ram, 0xFFF0, 0x20);
+    assert_eq!(image.segments[0].data.len(), 0x10);
+    assert_eq!(image.segments[0].data[0xF], 0x42);
+    assert_eq!(image.validate(), Ok(()));
+}
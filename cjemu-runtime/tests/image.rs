@@ -0,0 +1,138 @@
+//! Checks that ROM images survive being written and read back, and that
+//! malformed images are rejected with the right error.
+
+use cjemu_runtime::cjemu_api::{ReadableMemory, IMAGE_MAGIC, IMAGE_VERSION};
+use cjemu_runtime::{ImageError, Rom, RomImage};
+
+// The CRC-32 used by zip and PNG files, from its polynomial, for images
+// built by hand
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in bytes {
+        for i in 0..8 {
+            let bit = (byte >> i & 1) as u32 ^ (crc & 1);
+            crc >>= 1;
+            if bit != 0 {
+                crc ^= 0xEDB8_8320;
+            }
+        }
+    }
+    !crc
+}
+
+// An image with the given segments, each as an offset and its data, followed
+// by `extra` bytes and a valid checksum
+fn build(segments: &[(u16, &[u8])], extra: &[u8]) -> Vec<u8> {
+    let mut bytes = IMAGE_MAGIC.to_vec();
+    bytes.extend(&IMAGE_VERSION.to_le_bytes());
+    bytes.extend(&0x0100u16.to_le_bytes());
+    bytes.extend(&0x0000u16.to_le_bytes());
+    bytes.extend(&(segments.len() as u16).to_le_bytes());
+    for (offset, data) in segments {
+        bytes.extend(&offset.to_le_bytes());
+        bytes.extend(&(data.len() as u32).to_le_bytes());
+        bytes.extend(*data);
+    }
+    bytes.extend(extra);
+    let checksum = crc32(&bytes);
+    bytes.extend(&checksum.to_le_bytes());
+    bytes
+}
+
+fn example() -> RomImage {
+    RomImage::new(0x0100, 0x0000)
+        .with_segment(0x0000, vec![0x10, 0x20])
+        .with_segment(0x0100, vec![0x01, 0x02, 0x03])
+}
+
+#[test]
+fn round_trip() {
+    let image = example();
+    let bytes = image.to_bytes().unwrap();
+    assert_eq!(
+        bytes,
+        build(&[(0, &[0x10, 0x20]), (0x100, &[1, 2, 3])], &[])
+    );
+    assert_eq!(RomImage::parse(&bytes), Ok(image));
+
+    let rom = Rom::from_bytes(&bytes).unwrap();
+    assert_eq!(rom.entry_point(), 0x0100);
+    assert_eq!(rom.size(), 0x0103);
+    assert_eq!(rom.byte(0x0001), Some(0x20));
+    assert_eq!(rom.byte(0x0102), Some(0x03));
+}
+
+#[test]
+fn bad_magic() {
+    let mut bytes = example().to_bytes().unwrap();
+    bytes[..4].copy_from_slice(b"NOPE");
+    assert_eq!(RomImage::parse(&bytes), Err(ImageError::BadMagic(*b"NOPE")));
+}
+
+#[test]
+fn unsupported_version() {
+    let mut bytes = example().to_bytes().unwrap();
+    let version = IMAGE_VERSION + 1;
+    bytes[4..6].copy_from_slice(&version.to_le_bytes());
+    assert_eq!(
+        RomImage::parse(&bytes),
+        Err(ImageError::UnsupportedVersion(version))
+    );
+}
+
+#[test]
+fn checksum_mismatch() {
+    let mut bytes = example().to_bytes().unwrap();
+    let stored = crc32(&bytes[..bytes.len() - 4]);
+    let data = bytes.len() - 5;
+    bytes[data] ^= 0xFF;
+    assert_eq!(
+        RomImage::parse(&bytes),
+        Err(ImageError::ChecksumMismatch {
+            stored,
+            calculated: crc32(&bytes[..bytes.len() - 4]),
+        })
+    );
+}
+
+#[test]
+fn truncated() {
+    let bytes = build(&[(0, &[1, 2, 3, 4])], &[]);
+    // Cut the data of the segment short while keeping the checksum valid, so
+    // it fails at the start of the data, after the headers
+    let mut short = bytes[..bytes.len() - 6].to_vec();
+    let checksum = crc32(&short);
+    short.extend(&checksum.to_le_bytes());
+    assert_eq!(RomImage::parse(&short), Err(ImageError::Truncated(12 + 6)));
+}
+
+#[test]
+fn segment_overlap() {
+    let bytes = build(&[(0, &[1, 2, 3, 4]), (8, &[5]), (3, &[6, 7])], &[]);
+    assert_eq!(
+        RomImage::parse(&bytes),
+        Err(ImageError::SegmentOverlap(0, 2))
+    );
+    assert_eq!(
+        RomImage::new(0, 0)
+            .with_segment(2, vec![1, 2])
+            .with_segment(3, vec![3])
+            .to_bytes(),
+        Err(ImageError::SegmentOverlap(0, 1))
+    );
+}
+
+#[test]
+fn segment_out_of_range() {
+    let bytes = build(&[(0xFFFE, &[1, 2, 3])], &[]);
+    assert_eq!(
+        RomImage::parse(&bytes),
+        Err(ImageError::SegmentOutOfRange(0))
+    );
+}
+
+#[test]
+fn trailing_data() {
+    let bytes = build(&[(0, &[1, 2])], &[0xAA, 0xBB, 0xCC]);
+    assert_eq!(RomImage::parse(&bytes), Err(ImageError::TrailingData(3)));
+}
@@ -0,0 +1,107 @@
+//! Checks the keyboard's FIFO, its status bits and its interrupt through the
+//! device registers.
+
+use cjemu_runtime::cjemu_api::{BusError, Device};
+use cjemu_runtime::Keyboard;
+
+const IRQ: u8 = 1;
+
+#[test]
+fn keys_in_order() {
+    let (mut keyboard, input) = Keyboard::channel(Some(IRQ));
+    assert_eq!(keyboard.read(Keyboard::STATUS), Ok(0));
+    assert_eq!(keyboard.read(Keyboard::DATA), Ok(0));
+
+    input.type_text("ab\r");
+    assert_eq!(keyboard.read(Keyboard::STATUS), Ok(Keyboard::STATUS_READY));
+    // Peeking leaves the key to be read
+    assert_eq!(keyboard.peek(Keyboard::DATA), Some(b'a'));
+    assert_eq!(keyboard.read(Keyboard::DATA), Ok(b'a'));
+    input.press(b'c');
+    assert_eq!(keyboard.read(Keyboard::DATA), Ok(b'b'));
+    assert_eq!(keyboard.read(Keyboard::DATA), Ok(b'\n'));
+    assert_eq!(keyboard.read(Keyboard::DATA), Ok(b'c'));
+    assert_eq!(keyboard.read(Keyboard::DATA), Ok(0));
+    assert_eq!(keyboard.read(Keyboard::STATUS), Ok(0));
+}
+
+#[test]
+fn overflow() {
+    let (mut keyboard, input) = Keyboard::channel(Some(IRQ));
+    for i in 0..Keyboard::CAPACITY + 2 {
+        input.press(i as u8);
+    }
+
+    // The keys beyond the capacity are dropped, and reading the status clears
+    // the overflow bit
+    assert_eq!(
+        keyboard.read(Keyboard::STATUS),
+        Ok(Keyboard::STATUS_READY | Keyboard::STATUS_OVERFLOW)
+    );
+    assert_eq!(keyboard.read(Keyboard::STATUS), Ok(Keyboard::STATUS_READY));
+    for i in 0..Keyboard::CAPACITY {
+        assert_eq!(keyboard.read(Keyboard::DATA), Ok(i as u8));
+    }
+    assert_eq!(keyboard.read(Keyboard::STATUS), Ok(0));
+
+    // There is room again once the keys are read
+    input.press(b'x');
+    assert_eq!(keyboard.read(Keyboard::DATA), Ok(b'x'));
+    assert_eq!(keyboard.read(Keyboard::STATUS), Ok(0));
+}
+
+#[test]
+fn interrupt() {
+    let (mut keyboard, input) = Keyboard::channel(Some(IRQ));
+    input.press(b'a');
+    assert_eq!(keyboard.step(1), None);
+
+    keyboard
+        .write(Keyboard::CONTROL, Keyboard::CONTROL_INTERRUPT)
+        .unwrap();
+    assert_eq!(
+        keyboard.peek(Keyboard::CONTROL),
+        Some(Keyboard::CONTROL_INTERRUPT)
+    );
+    assert_eq!(keyboard.step(1), None);
+    input.press(b'b');
+    assert_eq!(keyboard.step(1), Some(IRQ));
+    assert_eq!(keyboard.step(1), None);
+
+    // Keys arriving while the FIFO is full still raise the interrupt
+    for _ in 0..Keyboard::CAPACITY {
+        input.press(b'c');
+    }
+    assert_eq!(keyboard.step(1), Some(IRQ));
+    input.press(b'd');
+    assert_eq!(keyboard.step(1), Some(IRQ));
+
+    let (mut keyboard, input) = Keyboard::channel(None);
+    keyboard
+        .write(Keyboard::CONTROL, Keyboard::CONTROL_INTERRUPT)
+        .unwrap();
+    input.press(b'a');
+    assert_eq!(keyboard.step(1), None);
+}
+
+#[test]
+fn read_only_registers() {
+    let (mut keyboard, input) = Keyboard::channel(Some(IRQ));
+    input.press(b'a');
+
+    assert_eq!(
+        keyboard.write(Keyboard::DATA, b'z'),
+        Err(BusError::ReadOnly(Keyboard::DATA))
+    );
+    assert_eq!(
+        keyboard.write(Keyboard::STATUS, 0),
+        Err(BusError::ReadOnly(Keyboard::STATUS))
+    );
+    // The high bytes are ignored
+    assert_eq!(keyboard.write(Keyboard::DATA + 1, b'z'), Ok(()));
+    assert_eq!(keyboard.write(Keyboard::STATUS + 1, 0xFF), Ok(()));
+
+    assert_eq!(keyboard.read(Keyboard::STATUS), Ok(Keyboard::STATUS_READY));
+    assert_eq!(keyboard.read(Keyboard::DATA), Ok(b'a'));
+    assert_eq!(keyboard.read(Keyboard::DATA), Ok(0));
+}
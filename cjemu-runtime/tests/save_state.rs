@@ -0,0 +1,200 @@
+//! Checks that save states restore a virtual machine exactly, and that states
+//! which don't fit the machine are rejected without changing it.
+
+use cjemu_runtime::cjemu_api::{Opcode, ReadableMemory, Register, VirtualMachine};
+use cjemu_runtime::{
+    BusLayout, CJEmuVirtualMachine, Console, Keyboard, KeyboardInput, MemoryBus, Ram, Rom,
+    RomImage, SaveState, SaveStateError, Timer,
+};
+
+const KEYBOARD_BASE: u16 = 0xF010;
+const TIMER_BASE: u16 = 0xF020;
+const RAM_SIZE: u32 = 0x8000;
+
+// Starts a periodic timer, then counts in `a`, storing each count to RAM
+fn program() -> Vec<u8> {
+    let [reload_low, reload_high] = (TIMER_BASE + Timer::RELOAD).to_le_bytes();
+    let [control_low, control_high] = (TIMER_BASE + Timer::CONTROL).to_le_bytes();
+    vec![
+        Opcode::LdA8 as u8,
+        50,
+        Opcode::StA16 as u8,
+        reload_low,
+        reload_high,
+        Opcode::LdA8 as u8,
+        Timer::CONTROL_ENABLE | Timer::CONTROL_PERIODIC,
+        Opcode::StA16 as u8,
+        control_low,
+        control_high,
+        Opcode::LdA8 as u8,
+        0,
+        // The loop starts at 12
+        Opcode::IncA as u8,
+        Opcode::StA16 as u8,
+        0x00,
+        0x90,
+        Opcode::Jmp as u8,
+        12,
+        0,
+    ]
+}
+
+fn machine_with(ram_size: u32, timer_base: u16) -> (CJEmuVirtualMachine, KeyboardInput) {
+    let image = RomImage::new(0, 0).with_segment(0, program());
+    let rom = Rom::from_image(&image).unwrap();
+    let (keyboard, input) = Keyboard::channel(Some(1));
+    let (console, _) = Console::channel();
+    let layout = BusLayout::default()
+        .with_device(0xF000, Box::new(console))
+        .with_device(KEYBOARD_BASE, Box::new(keyboard))
+        .with_device(timer_base, Box::new(Timer::new(Some(0))));
+    let ram = Ram::new(0, ram_size).unwrap();
+    let bus = MemoryBus::new(rom, ram, layout).unwrap();
+    (CJEmuVirtualMachine::new(bus), input)
+}
+
+fn machine() -> (CJEmuVirtualMachine, KeyboardInput) {
+    machine_with(RAM_SIZE, TIMER_BASE)
+}
+
+// A machine partway through the program, with keys waiting to be read
+fn running_machine() -> (CJEmuVirtualMachine, KeyboardInput) {
+    let (mut vm, input) = machine();
+    input.type_text("hi");
+    vm.run(137);
+    (vm, input)
+}
+
+fn timer_counter(vm: &CJEmuVirtualMachine) -> u16 {
+    let low = vm.bus().peek(TIMER_BASE + Timer::COUNTER).unwrap();
+    let high = vm.bus().peek(TIMER_BASE + Timer::COUNTER + 1).unwrap();
+    u16::from_le_bytes([low, high])
+}
+
+fn keyboard_status(vm: &CJEmuVirtualMachine) -> u8 {
+    vm.bus().peek(KEYBOARD_BASE + Keyboard::STATUS).unwrap()
+}
+
+#[test]
+fn round_trip() {
+    let (vm, _input) = running_machine();
+    let state = vm.save_state();
+    assert_eq!(SaveState::parse(&state.to_bytes()), Ok(state));
+}
+
+#[test]
+fn load_restores_machine() {
+    let (mut original, _input) = running_machine();
+    let bytes = original.save_state().to_bytes();
+
+    let (mut restored, _input) = machine();
+    restored
+        .load_state(&SaveState::parse(&bytes).unwrap())
+        .unwrap();
+
+    for &register in Register::ALL.iter() {
+        assert_eq!(
+            restored.register(register),
+            original.register(register),
+            "{:?}",
+            register
+        );
+    }
+    assert_eq!(restored.pc(), original.pc());
+    assert_eq!(restored.sp(), original.sp());
+    assert_eq!(restored.cycles(), original.cycles());
+    assert_eq!(
+        restored.instructions_retired(),
+        original.instructions_retired()
+    );
+    assert_eq!(restored.ram().byte(0x1000), original.ram().byte(0x1000));
+    assert_ne!(restored.ram().byte(0x1000), Some(0));
+    assert_eq!(timer_counter(&restored), timer_counter(&original));
+    assert_ne!(timer_counter(&restored), 0);
+    assert_eq!(
+        keyboard_status(&restored) & Keyboard::STATUS_READY,
+        Keyboard::STATUS_READY
+    );
+    assert_eq!(
+        restored.bus().peek(KEYBOARD_BASE + Keyboard::DATA),
+        Ok(b'h')
+    );
+
+    // Both carry on in step, including the timer interrupts
+    original.run(1000);
+    restored.run(1000);
+    assert_eq!(restored.save_state(), original.save_state());
+}
+
+#[test]
+fn unsupported_version() {
+    let (vm, _input) = running_machine();
+    let mut bytes = vm.save_state().to_bytes();
+    bytes[4..6].copy_from_slice(&(SaveState::VERSION + 1).to_le_bytes());
+    let err = SaveState::parse(&bytes).unwrap_err();
+    assert_eq!(
+        err,
+        SaveStateError::UnsupportedVersion(SaveState::VERSION + 1)
+    );
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "save state version {} isn't supported, only version {}",
+            SaveState::VERSION + 1,
+            SaveState::VERSION
+        )
+    );
+}
+
+#[test]
+fn ram_size_mismatch() {
+    let (vm, _input) = running_machine();
+    let state = vm.save_state();
+    let (mut smaller, _input) = machine_with(RAM_SIZE / 2, TIMER_BASE);
+    assert_eq!(
+        smaller.load_state(&state),
+        Err(SaveStateError::RamSizeMismatch {
+            saved: RAM_SIZE as usize,
+            expected: RAM_SIZE as usize / 2,
+        })
+    );
+}
+
+#[test]
+fn device_mismatch() {
+    let (vm, _input) = running_machine();
+    let state = vm.save_state();
+
+    let (mut moved, _input) = machine_with(RAM_SIZE, TIMER_BASE + 0x10);
+    assert_eq!(
+        moved.load_state(&state),
+        Err(SaveStateError::DeviceMismatch(2))
+    );
+
+    let mut missing = state.clone();
+    missing.devices.pop();
+    let (mut other, _input) = machine();
+    assert_eq!(
+        other.load_state(&missing),
+        Err(SaveStateError::DeviceMismatch(2))
+    );
+}
+
+#[test]
+fn rejected_device_rolls_back() {
+    let (vm, _input) = running_machine();
+    let mut state = vm.save_state();
+    // The keyboard loads its state before the timer rejects a short one
+    state.devices[2].data.pop();
+
+    let (mut other, input) = machine();
+    input.press(b'x');
+    other.run(30);
+    let before = other.save_state();
+    assert_eq!(
+        other.load_state(&state),
+        Err(SaveStateError::DeviceState(2))
+    );
+    assert_eq!(other.save_state(), before);
+    assert_eq!(other.bus().peek(KEYBOARD_BASE + Keyboard::DATA), Ok(b'x'));
+}
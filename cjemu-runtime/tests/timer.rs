@@ -0,0 +1,143 @@
+//! Checks when the timer expires, clears its status and raises its interrupt,
+//! both on its own and waking a virtual machine.
+
+use cjemu_runtime::cjemu_api::{
+    Device, MachineState, Opcode, Register, VirtualMachine, VECTOR_TABLE,
+};
+use cjemu_runtime::{BusLayout, CJEmuVirtualMachine, MemoryBus, Ram, Rom, RomImage, Timer};
+
+const IRQ: u8 = 3;
+const TIMER_BASE: u16 = 0xF020;
+
+fn write_word(timer: &mut Timer, offset: u16, value: u16) {
+    let [low, high] = value.to_le_bytes();
+    timer.write(offset, low).unwrap();
+    timer.write(offset + 1, high).unwrap();
+}
+
+fn started(reload: u16, prescaler: u16, control: u8) -> Timer {
+    let mut timer = Timer::new(Some(IRQ));
+    write_word(&mut timer, Timer::RELOAD, reload);
+    write_word(&mut timer, Timer::PRESCALER, prescaler);
+    timer
+        .write(
+            Timer::CONTROL,
+            Timer::CONTROL_ENABLE | Timer::CONTROL_INTERRUPT | control,
+        )
+        .unwrap();
+    timer
+}
+
+// The cycles, counted from 1, on which the timer raised its interrupt
+fn interrupts(timer: &mut Timer, cycles: u32) -> Vec<u32> {
+    (1..=cycles)
+        .filter(|_| timer.step(1) == Some(IRQ))
+        .collect()
+}
+
+#[test]
+fn one_shot() {
+    let mut timer = started(5, 0, 0);
+    assert_eq!(timer.step(4), None);
+    assert_eq!(timer.counter(), 1);
+    assert_eq!(timer.step(1), Some(IRQ));
+    assert!(!timer.running());
+    assert_eq!(timer.step(100), None);
+
+    let mut timer = started(5, 0, 0);
+    assert_eq!(interrupts(&mut timer, 100), vec![5]);
+}
+
+#[test]
+fn periodic() {
+    let mut timer = started(3, 1, Timer::CONTROL_PERIODIC);
+    assert_eq!(interrupts(&mut timer, 20), vec![6, 12, 18]);
+    assert!(timer.running());
+
+    // Several expiries in one step raise the interrupt once
+    let mut timer = started(3, 0, Timer::CONTROL_PERIODIC);
+    assert_eq!(timer.step(9), Some(IRQ));
+}
+
+#[test]
+fn reload_zero_counts_65536() {
+    let mut timer = started(0, 0, 0);
+    assert_eq!(timer.step(0xFFFF), None);
+    assert_eq!(timer.counter(), 1);
+    assert_eq!(timer.step(1), Some(IRQ));
+}
+
+#[test]
+fn interrupt_disabled() {
+    let mut timer = Timer::new(Some(IRQ));
+    write_word(&mut timer, Timer::RELOAD, 2);
+    timer.write(Timer::CONTROL, Timer::CONTROL_ENABLE).unwrap();
+    assert_eq!(timer.step(10), None);
+    assert_eq!(timer.peek(Timer::STATUS), Some(Timer::STATUS_EXPIRED));
+}
+
+#[test]
+fn status_clears_on_read() {
+    let mut timer = started(2, 0, 0);
+    assert_eq!(timer.read(Timer::STATUS), Ok(0));
+    timer.step(2);
+
+    // Peeking leaves the status alone
+    assert_eq!(timer.peek(Timer::STATUS), Some(Timer::STATUS_EXPIRED));
+    assert_eq!(timer.read(Timer::STATUS), Ok(Timer::STATUS_EXPIRED));
+    assert_eq!(timer.read(Timer::STATUS), Ok(0));
+}
+
+#[test]
+fn wakes_wait() {
+    const HANDLER: u16 = 0x0100;
+    let [reload_low, reload_high] = (TIMER_BASE + Timer::RELOAD).to_le_bytes();
+    let [control_low, control_high] = (TIMER_BASE + Timer::CONTROL).to_le_bytes();
+    let program = vec![
+        Opcode::LdA8 as u8,
+        50,
+        Opcode::StA16 as u8,
+        reload_low,
+        reload_high,
+        Opcode::LdA8 as u8,
+        Timer::CONTROL_ENABLE | Timer::CONTROL_INTERRUPT,
+        Opcode::StA16 as u8,
+        control_low,
+        control_high,
+        Opcode::Ei as u8,
+        Opcode::Wait as u8,
+        Opcode::Halt as u8,
+    ];
+    let handler = vec![Opcode::LdB8 as u8, 0x42, Opcode::Halt as u8];
+    let image = RomImage::new(0, 0)
+        .with_segment(0, program)
+        .with_segment(HANDLER, handler)
+        .with_segment(
+            VECTOR_TABLE + 2 * IRQ as u16,
+            HANDLER.to_le_bytes().to_vec(),
+        );
+    let layout = BusLayout::default().with_device(TIMER_BASE, Box::new(Timer::new(Some(IRQ))));
+    let bus = MemoryBus::new(
+        Rom::from_image(&image).unwrap(),
+        Ram::new(0, 0x8000).unwrap(),
+        layout,
+    )
+    .unwrap();
+    let mut vm = CJEmuVirtualMachine::new(bus);
+
+    // Waiting with no interrupt yet
+    vm.run(30);
+    assert_eq!(vm.state(), MachineState::Waiting);
+    assert_eq!(vm.pc(), 12);
+
+    // The handler halts with `a` still holding the control bits
+    vm.run(1000);
+    assert_eq!(
+        vm.state(),
+        MachineState::Halted {
+            exit_code: (Timer::CONTROL_ENABLE | Timer::CONTROL_INTERRUPT) as u16
+        }
+    );
+    assert_eq!(vm.register(Register::B), 0x42);
+    assert_eq!(vm.pc(), HANDLER + 3);
+}
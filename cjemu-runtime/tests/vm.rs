@@ -0,0 +1,690 @@
+//! Runs small programs through `CJEmuVirtualMachine`, checking the registers,
+//! memory and stop reasons they leave behind.
+
+use cjemu_runtime::cjemu_api::{
+    BusError, DebugVirtualMachine, Device, Instruction, MachineState, Opcode, Operand,
+    ReadableMemory, Register, VirtualMachine, INTERRUPT_CYCLES, VECTOR_TABLE,
+};
+use cjemu_runtime::{
+    Access, BusLayout, CJEmuVirtualMachine, Keyboard, MemoryBus, Ram, Rom, RomImage, StopReason,
+    TickError, Timer, WatchKind, Watchpoint,
+};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+const RAM_BASE: u16 = 0x8000;
+const TIMER_BASE: u16 = 0xF020;
+
+// A device counting the cycles it has been stepped by
+struct CycleCounter(Arc<AtomicU64>);
+
+impl Device for CycleCounter {
+    fn name(&self) -> &str {
+        "cycle counter"
+    }
+
+    fn size(&self) -> u16 {
+        1
+    }
+
+    fn read(&mut self, _offset: u16) -> Result<u8, BusError> {
+        Ok(0)
+    }
+
+    fn write(&mut self, offset: u16, _value: u8) -> Result<(), BusError> {
+        Err(BusError::ReadOnly(offset))
+    }
+
+    fn step(&mut self, cycles: u32) -> Option<u8> {
+        self.0.fetch_add(cycles as u64, Ordering::SeqCst);
+        None
+    }
+}
+
+// Encode a list of instructions into a program
+fn assemble(instructions: &[(Opcode, Operand)]) -> Vec<u8> {
+    instructions
+        .iter()
+        .flat_map(|&(opcode, operand)| {
+            Instruction::new(opcode, operand)
+                .expect("operand doesn't suit the opcode")
+                .encode()
+        })
+        .collect()
+}
+
+// An instruction without an operand
+fn op(opcode: Opcode) -> (Opcode, Operand) {
+    (opcode, Operand::None)
+}
+
+// A machine with the default layout, ROM holding each segment at its address
+// and RAM filling the upper half of the address space
+fn machine_with(segments: Vec<(u16, Vec<u8>)>, ram_size: u32) -> CJEmuVirtualMachine {
+    let image = segments
+        .into_iter()
+        .fold(RomImage::new(0, 0), |image, (address, data)| {
+            image.with_segment(address, data)
+        });
+    let rom = Rom::from_image(&image).unwrap();
+    CJEmuVirtualMachine::with_memory(rom, Ram::new(0, ram_size).unwrap())
+}
+
+fn machine(program: &[(Opcode, Operand)]) -> CJEmuVirtualMachine {
+    machine_with(vec![(0, assemble(program))], 0x8000)
+}
+
+// Run a program until it halts, returning its exit code
+fn exit_code(program: &[(Opcode, Operand)]) -> u16 {
+    match machine(program).run(10_000) {
+        StopReason::Halted { exit_code } => exit_code,
+        reason => panic!("program stopped without halting: {}", reason),
+    }
+}
+
+fn ram_word(vm: &CJEmuVirtualMachine, address: u16) -> u16 {
+    let offset = address - RAM_BASE;
+    u16::from_le_bytes([
+        vm.ram().byte(offset).unwrap(),
+        vm.ram().byte(offset + 1).unwrap(),
+    ])
+}
+
+#[test]
+fn add() {
+    let mut vm = machine(&[
+        (Opcode::LdA8, Operand::Imm8(15)),
+        (Opcode::LdB8, Operand::Imm8(32)),
+        op(Opcode::Add),
+    ]);
+    for _ in 0..3 {
+        vm.perform_tick().unwrap();
+    }
+    assert_eq!(vm.register(Register::A), 47);
+    assert_eq!(vm.register(Register::B), 32);
+    assert_eq!(vm.last_alu().value, 47);
+    assert!(!vm.last_alu().zero);
+    assert_eq!(vm.pc(), 5);
+    assert_eq!(vm.instructions_retired(), 3);
+}
+
+#[test]
+fn load_and_store() {
+    let mut vm = machine(&[
+        (Opcode::LdA16, Operand::Imm16(0x1234)),
+        (Opcode::StA16, Operand::Addr16(0x8100)),
+        (Opcode::LdB16, Operand::Imm16(0xABCD)),
+        (Opcode::StB16, Operand::Addr16(0x8102)),
+        // The 8-bit stores reach the zero page at the start of RAM
+        (Opcode::LdA8, Operand::Imm8(7)),
+        (Opcode::StA8, Operand::Addr8(0x10)),
+        (Opcode::StB8, Operand::Addr8(0x12)),
+        op(Opcode::Halt),
+    ]);
+    assert_eq!(vm.run(1000), StopReason::Halted { exit_code: 7 });
+    assert_eq!(ram_word(&vm, 0x8100), 0x1234);
+    assert_eq!(ram_word(&vm, 0x8102), 0xABCD);
+    assert_eq!(ram_word(&vm, 0x8010), 7);
+    assert_eq!(ram_word(&vm, 0x8012), 0xABCD);
+
+    let mut vm = machine(&[
+        (Opcode::LdA8, Operand::Imm8(1)),
+        (Opcode::StA16, Operand::Addr16(0x0001)),
+    ]);
+    assert_eq!(
+        vm.run(1000),
+        StopReason::Error(TickError::RomWrite {
+            pc: 2,
+            address: 0x0001
+        })
+    );
+    assert_eq!(vm.state(), MachineState::Faulted);
+}
+
+#[test]
+fn compare_and_branch() {
+    // `a` and `b` equal
+    assert_eq!(
+        exit_code(&[
+            (Opcode::LdA8, Operand::Imm8(5)),
+            (Opcode::LdB8, Operand::Imm8(5)),
+            op(Opcode::Cmp),
+            (Opcode::JmpZ, Operand::Addr16(9)),
+            op(Opcode::Halt),
+            // 9
+            op(Opcode::IncA),
+            op(Opcode::Halt),
+        ]),
+        6
+    );
+    // `a` less than `b` sets the borrow
+    assert_eq!(
+        exit_code(&[
+            (Opcode::LdA8, Operand::Imm8(3)),
+            (Opcode::LdB8, Operand::Imm8(5)),
+            op(Opcode::Cmp),
+            (Opcode::JmpNC, Operand::Addr16(14)),
+            (Opcode::JmpC, Operand::Addr16(12)),
+            op(Opcode::Halt),
+            // 12
+            op(Opcode::IncA),
+            op(Opcode::Halt),
+            // 14
+            op(Opcode::Halt),
+        ]),
+        4
+    );
+    // A loop counting up to `b`, jumping back with a negative offset
+    assert_eq!(
+        exit_code(&[
+            (Opcode::LdA8, Operand::Imm8(0)),
+            (Opcode::LdB8, Operand::Imm8(3)),
+            // 4
+            op(Opcode::IncA),
+            op(Opcode::Cmp),
+            (Opcode::JmpZ, Operand::Addr16(11)),
+            (Opcode::JmpRel, Operand::Rel8(-7)),
+            // 11
+            op(Opcode::Halt),
+        ]),
+        3
+    );
+}
+
+#[test]
+fn stack() {
+    let mut vm = machine(&[
+        (Opcode::LdA8, Operand::Imm8(0x11)),
+        op(Opcode::PushA),
+        (Opcode::LdA8, Operand::Imm8(0)),
+        op(Opcode::PopB),
+        (Opcode::Call, Operand::Addr16(10)),
+        op(Opcode::Halt),
+        // 10
+        (Opcode::LdA8, Operand::Imm8(9)),
+        op(Opcode::Ret),
+    ]);
+    let sp = vm.sp();
+    assert_eq!(vm.run(1000), StopReason::Halted { exit_code: 9 });
+    assert_eq!(vm.register(Register::B), 0x11);
+    assert_eq!(vm.sp(), sp);
+}
+
+#[test]
+fn stack_overflow_and_underflow() {
+    // A stack of two words
+    let program = assemble(&[op(Opcode::PushA), op(Opcode::PushA), op(Opcode::PushA)]);
+    let mut vm = machine_with(vec![(0, program)], 4);
+    assert_eq!(
+        vm.run(1000),
+        StopReason::Error(TickError::StackOverflow {
+            pc: 2,
+            address: RAM_BASE.wrapping_sub(2),
+        })
+    );
+    assert_eq!(vm.sp(), RAM_BASE);
+
+    let mut vm = machine_with(vec![(0, assemble(&[op(Opcode::PopA)]))], 4);
+    assert_eq!(
+        vm.run(1000),
+        StopReason::Error(TickError::StackUnderflow {
+            pc: 0,
+            address: RAM_BASE + 4,
+        })
+    );
+}
+
+#[test]
+fn interrupts() {
+    const HANDLER: u16 = 0x0100;
+    let main = assemble(&[
+        op(Opcode::Ei),
+        (Opcode::LdA8, Operand::Imm8(1)),
+        (Opcode::LdB8, Operand::Imm8(1)),
+        op(Opcode::Cmp),
+        op(Opcode::Halt),
+    ]);
+    // Changes the flags and `b`, then returns
+    let handler = assemble(&[
+        (Opcode::LdB8, Operand::Imm8(0x42)),
+        op(Opcode::PassB),
+        op(Opcode::RetI),
+    ]);
+    let vectors = (0..8)
+        .flat_map(|_| HANDLER.to_le_bytes().to_vec())
+        .collect();
+    let mut vm = machine_with(
+        vec![(0, main), (HANDLER, handler), (VECTOR_TABLE, vectors)],
+        0x8000,
+    );
+
+    // Run up to the `halt`, with the flags from the `cmp`
+    for _ in 0..4 {
+        vm.perform_tick().unwrap();
+    }
+    let flags = vm.register(Register::Flags);
+    let sp = vm.sp();
+    assert!(vm.last_alu().zero);
+
+    // Interrupts are taken before the next instruction
+    vm.raise_interrupt(2).unwrap();
+    vm.perform_tick().unwrap();
+    assert_eq!(vm.pc(), HANDLER);
+    assert_eq!(vm.sp(), sp.wrapping_sub(4));
+    assert!(!vm.interrupts().enabled());
+    assert_eq!(vm.interrupts().pending(), 0);
+
+    // The handler returns to the `halt` with the flags as they were
+    for _ in 0..3 {
+        vm.perform_tick().unwrap();
+    }
+    assert_eq!(vm.pc(), 6);
+    assert_eq!(vm.sp(), sp);
+    assert_eq!(vm.register(Register::B), 0x42);
+    assert_eq!(vm.register(Register::Flags), flags);
+    assert!(vm.interrupts().enabled());
+
+    // Masked lines stay pending
+    let mut vm = machine(&[
+        op(Opcode::Ei),
+        (Opcode::SetIM, Operand::Imm8(1 << 3)),
+        op(Opcode::NoOp),
+        op(Opcode::Halt),
+    ]);
+    vm.perform_tick().unwrap();
+    vm.perform_tick().unwrap();
+    vm.raise_interrupt(3).unwrap();
+    assert_eq!(vm.run(1000), StopReason::Halted { exit_code: 0 });
+    assert_eq!(vm.interrupts().pending(), 1 << 3);
+}
+
+#[test]
+fn interrupt_overflowing_stack() {
+    // A stack of one word, with room for the return address but not the flags
+    let program = assemble(&[op(Opcode::Ei), op(Opcode::NoOp)]);
+    let vectors = (0..8)
+        .flat_map(|_| 0x0100u16.to_le_bytes().to_vec())
+        .collect();
+    let mut vm = machine_with(vec![(0, program), (VECTOR_TABLE, vectors)], 2);
+    vm.perform_tick().unwrap();
+    let sp = vm.sp();
+
+    vm.raise_interrupt(2).unwrap();
+    assert_eq!(
+        vm.perform_tick(),
+        Err(TickError::StackOverflow {
+            pc: 1,
+            address: sp.wrapping_sub(2),
+        })
+    );
+    assert_eq!(vm.pc(), 1);
+    assert_eq!(vm.sp(), sp);
+    assert_eq!(vm.interrupts().pending(), 1 << 2);
+    assert!(vm.interrupts().enabled());
+}
+
+#[test]
+fn address_modes() {
+    let mut vm = machine(&[
+        (Opcode::LdB16, Operand::Imm16(0x8010)),
+        (Opcode::LdA16, Operand::Imm16(0xBEEF)),
+        op(Opcode::StAIP),
+        (Opcode::LdA16, Operand::Imm16(0xCAFE)),
+        op(Opcode::StAI),
+        // Index the second word from the start of the array
+        (Opcode::LdB8, Operand::Imm8(2)),
+        (Opcode::LdAX, Operand::Addr16(0x8010)),
+        (Opcode::StAX, Operand::Addr16(0x8020)),
+        (Opcode::LdB16, Operand::Imm16(0x8010)),
+        op(Opcode::LdAIP),
+        op(Opcode::Halt),
+    ]);
+    assert_eq!(vm.run(1000), StopReason::Halted { exit_code: 0xBEEF });
+    assert_eq!(ram_word(&vm, 0x8010), 0xBEEF);
+    assert_eq!(ram_word(&vm, 0x8012), 0xCAFE);
+    assert_eq!(ram_word(&vm, 0x8022), 0xCAFE);
+    assert_eq!(vm.register(Register::B), 0x8012);
+}
+
+#[test]
+fn mov_and_swap() {
+    let mut vm = machine(&[
+        (Opcode::LdA8, Operand::Imm8(1)),
+        (Opcode::LdB8, Operand::Imm8(2)),
+        (Opcode::Mov, Operand::Registers(Register::C, Register::A)),
+        (Opcode::Swap, Operand::Registers(Register::A, Register::B)),
+        (Opcode::Mov, Operand::Registers(Register::D, Register::B)),
+        // Save the flags of the `cmp`, then put them back
+        op(Opcode::Cmp),
+        (
+            Opcode::Mov,
+            Operand::Registers(Register::B, Register::Flags),
+        ),
+        op(Opcode::PassA),
+        (
+            Opcode::Mov,
+            Operand::Registers(Register::Flags, Register::B),
+        ),
+        op(Opcode::Halt),
+    ]);
+    assert_eq!(vm.run(1000), StopReason::Halted { exit_code: 2 });
+    assert_eq!(vm.register(Register::C), 1);
+    assert_eq!(vm.register(Register::D), 1);
+    // 2 - 1 is neither zero nor negative, and has odd parity
+    assert!(!vm.last_alu().zero && !vm.last_alu().negative && !vm.last_alu().parity);
+
+    let mut vm = machine_with(vec![(0, vec![Opcode::Mov as u8, 0x05])], 0x8000);
+    assert_eq!(
+        vm.run(1000),
+        StopReason::Error(TickError::IllegalRegister {
+            pc: 0,
+            address: 1,
+            register: 5,
+        })
+    );
+}
+
+#[test]
+fn carry_chains() {
+    // 0x0001_FFFF + 0x0002_0001, then take 0x0002_0001 back off, keeping the
+    // low words at 0x8000 and the high words in `a`
+    let mut vm = machine(&[
+        (Opcode::LdA16, Operand::Imm16(0xFFFF)),
+        (Opcode::LdB16, Operand::Imm16(0x0001)),
+        op(Opcode::Add),
+        (Opcode::StA16, Operand::Addr16(0x8000)),
+        (Opcode::LdA16, Operand::Imm16(0x0001)),
+        (Opcode::LdB16, Operand::Imm16(0x0002)),
+        op(Opcode::Adc),
+        (Opcode::StA16, Operand::Addr16(0x8002)),
+        (Opcode::LdA16, Operand::Imm16(0x0000)),
+        (Opcode::LdB16, Operand::Imm16(0x0001)),
+        op(Opcode::Sub),
+        (Opcode::StA16, Operand::Addr16(0x8004)),
+        (Opcode::LdA16, Operand::Imm16(0x0004)),
+        (Opcode::LdB16, Operand::Imm16(0x0002)),
+        op(Opcode::Sbc),
+        op(Opcode::Halt),
+    ]);
+    assert_eq!(vm.run(1000), StopReason::Halted { exit_code: 0x0001 });
+    assert_eq!(ram_word(&vm, 0x8000), 0x0000);
+    assert_eq!(ram_word(&vm, 0x8002), 0x0004);
+    assert_eq!(ram_word(&vm, 0x8004), 0xFFFF);
+    assert!(!vm.last_alu().carry_out);
+
+    // Shift 0x4000_8001 left a bit across both words and back again, with
+    // the bit between the words carried over
+    let mut vm = machine(&[
+        (Opcode::LdB8, Operand::Imm8(1)),
+        (Opcode::LdA16, Operand::Imm16(0x8001)),
+        op(Opcode::PassA),
+        op(Opcode::RotLC),
+        (Opcode::StA16, Operand::Addr16(0x8000)),
+        (Opcode::LdA16, Operand::Imm16(0x4000)),
+        op(Opcode::RotLC),
+        (Opcode::StA16, Operand::Addr16(0x8002)),
+        op(Opcode::RotRC),
+        (Opcode::StA16, Operand::Addr16(0x8004)),
+        (Opcode::LdA16, Operand::Imm16(0x0002)),
+        op(Opcode::RotRC),
+        op(Opcode::Halt),
+    ]);
+    assert_eq!(vm.run(1000), StopReason::Halted { exit_code: 0x8001 });
+    assert_eq!(ram_word(&vm, 0x8000), 0x0002);
+    assert_eq!(ram_word(&vm, 0x8002), 0x8001);
+    assert_eq!(ram_word(&vm, 0x8004), 0x4000);
+    assert!(!vm.last_alu().carry_out);
+}
+
+#[test]
+fn multiply_and_divide() {
+    let mut vm = machine(&[
+        (Opcode::LdA16, Operand::Imm16(0x1234)),
+        (Opcode::LdB16, Operand::Imm16(0x0100)),
+        op(Opcode::Mul),
+        op(Opcode::Halt),
+    ]);
+    assert_eq!(vm.run(1000), StopReason::Halted { exit_code: 0x3400 });
+    assert_eq!(vm.register(Register::B), 0x0012);
+
+    // -2 * 3 sign extends into the high word
+    let mut vm = machine(&[
+        (Opcode::LdA16, Operand::Imm16(-2i16 as u16)),
+        (Opcode::LdB8, Operand::Imm8(3)),
+        op(Opcode::IMul),
+        op(Opcode::Halt),
+    ]);
+    assert_eq!(
+        vm.run(1000),
+        StopReason::Halted {
+            exit_code: -6i16 as u16
+        }
+    );
+    assert_eq!(vm.register(Register::B), 0xFFFF);
+
+    assert_eq!(
+        exit_code(&[
+            (Opcode::LdA8, Operand::Imm8(47)),
+            (Opcode::LdB8, Operand::Imm8(5)),
+            op(Opcode::Div),
+            op(Opcode::Halt),
+        ]),
+        9
+    );
+    assert_eq!(
+        exit_code(&[
+            (Opcode::LdA8, Operand::Imm8(47)),
+            (Opcode::LdB8, Operand::Imm8(5)),
+            op(Opcode::Mod),
+            op(Opcode::Halt),
+        ]),
+        2
+    );
+}
+
+#[test]
+fn divide_by_zero() {
+    for &opcode in &[Opcode::Div, Opcode::IDiv, Opcode::Mod, Opcode::IMod] {
+        let mut vm = machine(&[
+            (Opcode::LdA8, Operand::Imm8(47)),
+            (Opcode::LdB8, Operand::Imm8(0)),
+            op(opcode),
+            op(Opcode::Halt),
+        ]);
+        let error = TickError::DivideByZero { pc: 4, address: 4 };
+        assert_eq!(vm.run(1000), StopReason::Error(error), "{:?}", opcode);
+        assert_eq!(vm.pc(), 4);
+        assert_eq!(vm.register(Register::A), 47);
+        assert_eq!(vm.state(), MachineState::Faulted);
+        assert_eq!(
+            vm.perform_tick(),
+            Err(TickError::Halted { pc: 4, address: 4 })
+        );
+    }
+}
+
+#[test]
+fn cycle_counts() {
+    const HANDLER: u16 = 0x0100;
+    let main = assemble(&[
+        op(Opcode::Ei),
+        (Opcode::LdA16, Operand::Imm16(3)),
+        (Opcode::LdB8, Operand::Imm8(3)),
+        op(Opcode::PushA),
+        op(Opcode::Mul),
+        op(Opcode::PopB),
+        op(Opcode::Halt),
+    ]);
+    let handler = assemble(&[op(Opcode::RetI)]);
+    let image = RomImage::new(0, 0)
+        .with_segment(0, main)
+        .with_segment(HANDLER, handler)
+        .with_segment(VECTOR_TABLE, HANDLER.to_le_bytes().to_vec());
+    let stepped = Arc::new(AtomicU64::new(0));
+    let layout = BusLayout::default().with_device(0xF000, Box::new(CycleCounter(stepped.clone())));
+    let bus = MemoryBus::new(
+        Rom::from_image(&image).unwrap(),
+        Ram::new(0, 0x8000).unwrap(),
+        layout,
+    )
+    .unwrap();
+    let mut vm = CJEmuVirtualMachine::new(bus);
+
+    // The interrupt is raised after the `lda16`, so it is taken in place of
+    // the `ldb8`
+    let ticks = [
+        Opcode::Ei.cycles(),
+        Opcode::LdA16.cycles(),
+        INTERRUPT_CYCLES,
+        Opcode::RetI.cycles(),
+        Opcode::LdB8.cycles(),
+        Opcode::PushA.cycles(),
+        Opcode::Mul.cycles(),
+        Opcode::PopB.cycles(),
+        Opcode::Halt.cycles(),
+    ];
+    let mut expected = 0;
+    for (i, &cycles) in ticks.iter().enumerate() {
+        if i == 2 {
+            vm.raise_interrupt(0).unwrap();
+        }
+        vm.perform_tick().unwrap();
+        expected += cycles as u64;
+        assert_eq!(vm.cycles(), expected, "tick {}", i);
+        assert_eq!(stepped.load(Ordering::SeqCst), expected, "tick {}", i);
+    }
+    assert_eq!(vm.state(), MachineState::Halted { exit_code: 9 });
+    assert_eq!(vm.instructions_retired(), ticks.len() as u64 - 1);
+}
+
+#[test]
+fn breakpoints_and_watchpoints() {
+    let mut vm = machine(&[
+        (Opcode::LdA8, Operand::Imm8(1)),
+        op(Opcode::IncA),
+        op(Opcode::IncA),
+        (Opcode::StA16, Operand::Addr16(0x8000)),
+        op(Opcode::Halt),
+    ]);
+    vm.breakpoints_mut().add_breakpoint(3);
+    vm.breakpoints_mut()
+        .add_watchpoint(Watchpoint::new(0x8001, 0x8001, WatchKind::Write));
+
+    // Paused before the instruction at the breakpoint
+    assert_eq!(
+        vm.run(1000),
+        StopReason::Breakpoint(TickError::Breakpoint { pc: 3, address: 3 })
+    );
+    assert_eq!(vm.register(Register::A), 2);
+    assert_eq!(vm.state(), MachineState::Running);
+
+    // Paused after the store matching the watchpoint
+    assert_eq!(
+        vm.run(1000),
+        StopReason::Breakpoint(TickError::Watchpoint {
+            pc: 4,
+            address: 0x8001,
+            access: Access::Write,
+            value: 0,
+        })
+    );
+    assert_eq!(vm.pc(), 7);
+    assert_eq!(ram_word(&vm, 0x8000), 3);
+
+    assert_eq!(vm.run(1000), StopReason::Halted { exit_code: 3 });
+}
+
+#[test]
+fn halt_and_wait() {
+    let mut vm = machine(&[
+        op(Opcode::Wait),
+        op(Opcode::IncA),
+        op(Opcode::Wait),
+        op(Opcode::IncA),
+        op(Opcode::Wait),
+        op(Opcode::Halt),
+    ]);
+    assert_eq!(vm.run(100), StopReason::Waiting);
+    assert_eq!(vm.state(), MachineState::Waiting);
+
+    // Each wait needs an interrupt of its own while interrupts are disabled
+    vm.raise_interrupt(2).unwrap();
+    assert_eq!(vm.run(100), StopReason::Waiting);
+    assert_eq!(vm.register(Register::A), 1);
+    vm.raise_interrupt(2).unwrap();
+    assert_eq!(vm.run(100), StopReason::Waiting);
+    assert_eq!(vm.register(Register::A), 2);
+    vm.raise_interrupt(2).unwrap();
+    assert_eq!(vm.run(100), StopReason::Halted { exit_code: 2 });
+
+    assert_eq!(vm.state(), MachineState::Halted { exit_code: 2 });
+    assert_eq!(
+        vm.perform_tick(),
+        Err(TickError::Halted { pc: 6, address: 6 })
+    );
+}
+
+#[test]
+fn run_stays_within_budget() {
+    let mut vm = machine(&[
+        (Opcode::LdA8, Operand::Imm8(9)),
+        (Opcode::LdB8, Operand::Imm8(2)),
+        op(Opcode::Div),
+        (Opcode::Jmp, Operand::Addr16(0)),
+    ]);
+    for budget in 0..40 {
+        let cycles = vm.cycles();
+        assert_eq!(vm.run(budget), StopReason::BudgetExhausted);
+        assert!(vm.cycles() - cycles <= budget, "budget {}", budget);
+    }
+}
+
+#[test]
+fn stopped_machine_reports_why() {
+    let mut vm = machine(&[(Opcode::LdB8, Operand::Imm8(0)), op(Opcode::Div)]);
+    let error = TickError::DivideByZero { pc: 2, address: 2 };
+    assert_eq!(vm.run(1000), StopReason::Error(error));
+    for &budget in &[0, 1, 1000] {
+        assert_eq!(vm.run(budget), StopReason::Error(error));
+    }
+
+    let mut vm = machine(&[(Opcode::LdA8, Operand::Imm8(3)), op(Opcode::Halt)]);
+    assert_eq!(vm.run(1000), StopReason::Halted { exit_code: 3 });
+    for &budget in &[0, 1, 1000] {
+        assert_eq!(vm.run(budget), StopReason::Halted { exit_code: 3 });
+    }
+}
+
+#[test]
+fn reset() {
+    let [control_low, control_high] = (TIMER_BASE + Timer::CONTROL).to_le_bytes();
+    let program = vec![
+        Opcode::LdA8 as u8,
+        Timer::CONTROL_ENABLE | Timer::CONTROL_PERIODIC | Timer::CONTROL_INTERRUPT,
+        Opcode::StA16 as u8,
+        control_low,
+        control_high,
+        Opcode::PushA as u8,
+        Opcode::Halt as u8,
+    ];
+    let rom = Rom::from_image(&RomImage::new(0, 0).with_segment(0, program)).unwrap();
+    let (keyboard, input) = Keyboard::channel(Some(1));
+    let layout = BusLayout::default()
+        .with_device(0xF010, Box::new(keyboard))
+        .with_device(TIMER_BASE, Box::new(Timer::new(Some(0))));
+    let bus = MemoryBus::new(rom, Ram::new(0, 0x8000).unwrap(), layout).unwrap();
+    let mut vm = CJEmuVirtualMachine::new(bus);
+    let sp = vm.sp();
+
+    input.press(b'x');
+    assert_eq!(vm.run(1000), StopReason::Halted { exit_code: 7 });
+    vm.reset();
+
+    assert_eq!(vm.state(), MachineState::Running);
+    assert_eq!((vm.pc(), vm.sp(), vm.cycles()), (0, sp, 0));
+    assert_eq!(vm.register(Register::A), 0);
+    assert_eq!(vm.bus().peek(TIMER_BASE + Timer::CONTROL), Ok(0));
+    assert_eq!(vm.bus().peek(0xF010 + Keyboard::STATUS), Ok(0));
+    // Memory is left alone
+    assert_eq!(vm.ram().byte(0x7FFE), Some(7));
+}
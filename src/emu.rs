@@ -1,23 +1,28 @@
-use cjemu_runtime::cjemu_api::VirtualMachine;
-use cjemu_runtime::CJEmuVirtualMachine;
-use std::sync::{mpsc, Arc, RwLock};
+use cjemu_runtime::cjemu_api::{Opcode, VirtualMachine, INTERRUPT_CYCLES};
+use cjemu_runtime::{CJEmuVirtualMachine, StopReason};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
 use std::thread;
 use std::thread::JoinHandle;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant};
+
+// How much of the machine's time to run while holding the lock, in seconds
+const BATCH_SECS: f64 = 0.01;
 
 #[derive(Copy, Clone)]
 #[allow(dead_code)]
 enum EmulationEvent {
     Exit,
+    // Exit once the runs sent before it are done
+    Finish,
     Tick,
-    Cycle { ticks: u64, ticks_per_second: f64 },
+    Cycle { cycles: u64, cycles_per_second: f64 },
 }
 
 unsafe impl Sync for EmulationEvent {}
 unsafe impl Send for EmulationEvent {}
 
 pub struct EmulationHandler {
-    virtual_machine: Arc<RwLock<CJEmuVirtualMachine>>,
+    virtual_machine: Arc<Mutex<CJEmuVirtualMachine>>,
 
     join_handle: Option<JoinHandle<()>>,
     event_sender: mpsc::Sender<EmulationEvent>,
@@ -33,7 +38,7 @@ impl EmulationHandler {
         let mut vm = Self {
             join_handle: None,
 
-            virtual_machine: Arc::new(RwLock::new(virtual_machine)),
+            virtual_machine: Arc::new(Mutex::new(virtual_machine)),
             event_sender,
 
             has_exit: false,
@@ -44,107 +49,142 @@ impl EmulationHandler {
 
     fn start_loop(
         event_receiver: mpsc::Receiver<EmulationEvent>,
-        virtual_machine: Arc<RwLock<CJEmuVirtualMachine>>,
+        virtual_machine: Arc<Mutex<CJEmuVirtualMachine>>,
     ) -> JoinHandle<()> {
         thread::spawn(move || {
-            println!("starting emulation loop");
+            eprintln!("starting emulation loop");
+
+            // An event that arrived during a run and cut it short
+            let mut next_event = None;
 
             'main_loop: loop {
-                match event_receiver
-                    .recv()
-                    .expect("failed to receive emulation event")
-                {
-                    EmulationEvent::Exit => break 'main_loop,
-                    EmulationEvent::Tick => {
-                        println!("ticking virtual machine");
-                        virtual_machine
-                            .write()
-                            .expect("failed to lock write access for virtual machine")
-                            .perform_tick()
-                            .expect("failed to tick the virtual machine");
-                    }
+                let event = match next_event.take() {
+                    Some(event) => event,
+                    None => event_receiver
+                        .recv()
+                        .expect("failed to receive emulation event"),
+                };
+                match event {
+                    EmulationEvent::Exit | EmulationEvent::Finish => break 'main_loop,
+                    EmulationEvent::Tick => Self::tick_machine(&virtual_machine),
                     EmulationEvent::Cycle {
-                        ticks,
-                        ticks_per_second,
+                        cycles,
+                        cycles_per_second,
                     } => {
-                        println!(
+                        eprintln!(
                             "running {} cycles on the virtual machine at {} cycles per second",
-                            ticks, ticks_per_second
+                            cycles, cycles_per_second
                         );
 
-                        let mut past_ticks = 0;
-                        let mut last_tick_time = SystemTime::now();
-                        let mut last_print_time = SystemTime::now();
-                        let mut last_print_ticks = 0;
-                        let secs_per_tick = 1.0 / ticks_per_second;
-
-                        while past_ticks < ticks {
-                            // Get the time since the last tick
-                            let current_time = SystemTime::now();
-                            let elapsed_time_secs = current_time
-                                .duration_since(last_tick_time)
-                                .unwrap_or_else(|_| {
-                                    panic!(
-                                        "failed to get duration from {:?} to {:?}",
-                                        last_tick_time, current_time
-                                    )
-                                })
-                                .as_secs_f64();
-
-                            let elapsed_print_secs = current_time
-                                .duration_since(last_print_time)
-                                .unwrap_or_else(|_| {
-                                    panic!(
-                                        "failed to get duration from {:?} to {:?}",
-                                        last_print_time, current_time
-                                    )
-                                })
-                                .as_secs_f64();
-                            if elapsed_print_secs > 1.0 {
-                                last_print_time = current_time;
-                                let t = past_ticks - last_print_ticks;
-                                last_print_ticks = past_ticks;
-                                println!("processed {} cycles (of {}) in 1 second", t, ticks);
-                            }
+                        // Run the machine a batch of cycles at a time, only
+                        // holding the lock for each batch, and sleeping after
+                        // each one until real time catches up with it
+                        // Each batch is at least long enough for the slowest
+                        // instruction, so that every batch gets somewhere
+                        let slowest = Opcode::ALL
+                            .iter()
+                            .map(|opcode| opcode.cycles())
+                            .chain(std::iter::once(INTERRUPT_CYCLES))
+                            .max()
+                            .unwrap_or(1) as u64;
+                        let batch_cycles = ((cycles_per_second * BATCH_SECS) as u64).max(slowest);
+                        let start_time = Instant::now();
+                        let mut past_cycles = 0;
+                        let mut last_print_time = start_time;
+                        let mut last_print_cycles = 0;
 
-                            // Check if a tick needs to happen yet
-                            if elapsed_time_secs > secs_per_tick {
-                                last_tick_time = current_time;
+                        while past_cycles < cycles {
+                            let (stop_reason, ran) = {
+                                let mut virtual_machine = virtual_machine
+                                    .lock()
+                                    .expect("failed to lock the virtual machine");
+                                let cycles_before = virtual_machine.cycles();
+                                let stop_reason =
+                                    virtual_machine.run(batch_cycles.min(cycles - past_cycles));
+                                (stop_reason, virtual_machine.cycles() - cycles_before)
+                            };
+                            past_cycles += ran;
 
-                                // Tick the machine
-                                virtual_machine
-                                    .write()
-                                    .expect("failed to lock write access for virtual machine")
-                                    .perform_tick()
-                                    .expect("failed to tick the virtual machine");
+                            // Give up on the rest of the cycles if the machine
+                            // halts or stops, or if they are too few for the
+                            // next instruction, but keep waiting machines
+                            // running so that devices can wake them
+                            match stop_reason {
+                                StopReason::BudgetExhausted if ran == 0 => break,
+                                StopReason::BudgetExhausted | StopReason::Waiting => {}
+                                _ => {
+                                    eprintln!("virtual machine stopped: {}", stop_reason);
+                                    break;
+                                }
+                            }
 
-                                // Increment the tick counter
-                                past_ticks += 1;
+                            if last_print_time.elapsed().as_secs_f64() > 1.0 {
+                                last_print_time = Instant::now();
+                                let c = past_cycles - last_print_cycles;
+                                last_print_cycles = past_cycles;
+                                eprintln!("processed {} cycles (of {}) in 1 second", c, cycles);
+                            }
 
-                                // If we have to wait more than 10 milliseconds,
-                                // we might as well sleep this thread
-                                if secs_per_tick > 0.010 {
-                                    std::thread::sleep(Duration::from_millis(1))
+                            // Wait for real time to catch up, but stop waiting for
+                            // events: exiting leaves the thread and a new run
+                            // replaces this one
+                            let machine_time =
+                                Duration::from_secs_f64(past_cycles as f64 / cycles_per_second);
+                            let ahead = machine_time
+                                .checked_sub(start_time.elapsed())
+                                .unwrap_or_default();
+                            match event_receiver.recv_timeout(ahead) {
+                                Ok(EmulationEvent::Exit)
+                                | Err(mpsc::RecvTimeoutError::Disconnected) => break 'main_loop,
+                                Ok(EmulationEvent::Tick) => Self::tick_machine(&virtual_machine),
+                                Ok(EmulationEvent::Finish) => {
+                                    next_event = Some(EmulationEvent::Finish)
+                                }
+                                Ok(event) => {
+                                    next_event = Some(event);
+                                    break;
                                 }
+                                Err(mpsc::RecvTimeoutError::Timeout) => {}
                             }
                         }
 
-                        println!("processed {} cycles", ticks);
+                        eprintln!("processed {} cycles", past_cycles);
                     }
                 }
             }
 
-            println!("exiting emulation loop");
+            eprintln!("exiting emulation loop");
         })
     }
 
+    fn tick_machine(virtual_machine: &Mutex<CJEmuVirtualMachine>) {
+        eprintln!("ticking virtual machine");
+        let ticked = virtual_machine
+            .lock()
+            .expect("failed to lock the virtual machine")
+            .perform_tick();
+        if let Err(err) = ticked {
+            eprintln!("virtual machine stopped: {}", err);
+        }
+    }
+
+    // Exit the emulation thread, abandoning any run in progress
     pub fn exit(&mut self) {
+        self.stop(EmulationEvent::Exit);
+    }
+
+    // Exit the emulation thread once the runs already sent have played out
+    pub fn finish(&mut self) {
+        self.stop(EmulationEvent::Finish);
+    }
+
+    fn stop(&mut self, event: EmulationEvent) {
         if !self.has_exit {
             self.has_exit = true;
 
             // Send the exit event signal to the emulator
             self.event_sender
-                .send(EmulationEvent::Exit)
+                .send(event)
                 .map_err(|_| ())
                 .expect("failed to send exit event to emulation thread");
 
@@ -156,17 +196,27 @@ impl EmulationHandler {
         }
     }
 
+    // Lock the virtual machine, waiting for the tick in progress to finish
+    pub fn virtual_machine(&self) -> MutexGuard<'_, CJEmuVirtualMachine> {
+        self.virtual_machine
+            .lock()
+            .expect("failed to lock the virtual machine")
+    }
+
     pub fn tick(&mut self) {
         self.event_sender
             .send(EmulationEvent::Tick)
             .expect("failed to send tick message to emulation thread");
     }
 
-    pub fn cycle(&mut self, ticks: u64, ticks_per_second: f64) {
+    // Run the virtual machine for up to `cycles` machine cycles, pacing it
+    // to `cycles_per_second` in batches of instructions, in place of any run
+    // still in progress
+    pub fn cycle(&mut self, cycles: u64, cycles_per_second: f64) {
         self.event_sender
             .send(EmulationEvent::Cycle {
-                ticks,
-                ticks_per_second,
+                cycles,
+                cycles_per_second,
             })
             .expect("failed to send tick message to emulation thread");
     }
@@ -177,3 +227,56 @@ impl Drop for EmulationHandler {
         self.exit();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cjemu_runtime::{Ram, Rom, RomImage};
+
+    #[test]
+    fn exit_during_run() {
+        // A program that spins forever
+        let program = vec![Opcode::Jmp as u8, 0, 0];
+        let rom = Rom::from_image(&RomImage::new(0, 0).with_segment(0, program)).unwrap();
+        let virtual_machine = CJEmuVirtualMachine::with_memory(rom, Ram::new(0, 0x100).unwrap());
+        let mut emulation_handler = EmulationHandler::new(virtual_machine);
+
+        // Two runs that would take over a minute each in real time
+        emulation_handler.cycle(100_000, 1000.0);
+        emulation_handler.cycle(100_000, 1000.0);
+        thread::sleep(Duration::from_millis(50));
+        assert!(emulation_handler.virtual_machine().cycles() > 0);
+
+        let start_time = Instant::now();
+        emulation_handler.exit();
+        assert!(start_time.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn new_run_replaces_run() {
+        let program = vec![Opcode::Jmp as u8, 0, 0];
+        let rom = Rom::from_image(&RomImage::new(0, 0).with_segment(0, program)).unwrap();
+        let virtual_machine = CJEmuVirtualMachine::with_memory(rom, Ram::new(0, 0x100).unwrap());
+        let mut emulation_handler = EmulationHandler::new(virtual_machine);
+
+        // The short run cuts the long one off instead of waiting behind it
+        emulation_handler.cycle(100_000, 1000.0);
+        emulation_handler.cycle(50, 1000.0);
+        thread::sleep(Duration::from_millis(300));
+        assert!(emulation_handler.virtual_machine().cycles() < 100);
+    }
+
+    #[test]
+    fn finish_after_run() {
+        let program = vec![Opcode::Jmp as u8, 0, 0];
+        let rom = Rom::from_image(&RomImage::new(0, 0).with_segment(0, program)).unwrap();
+        let virtual_machine = CJEmuVirtualMachine::with_memory(rom, Ram::new(0, 0x100).unwrap());
+        let mut emulation_handler = EmulationHandler::new(virtual_machine);
+
+        let start_time = Instant::now();
+        emulation_handler.cycle(100, 1000.0);
+        emulation_handler.finish();
+        assert!(start_time.elapsed() >= Duration::from_millis(90));
+        assert!(emulation_handler.virtual_machine().cycles() > 90);
+    }
+}
@@ -6,19 +6,47 @@ const APP_DIR_REL: &str = "cjemu";
 const FONT_DIR_REL: &str = "font";
 // Font location relative to the `cjemu` font directory
 const FONT_REL: &str = "main_font.ttf";
+// The save state directory relative to the `cjemu` directory
+const SAVE_STATE_DIR_REL: &str = "states";
+// The number of save state slots in the state menu
+const SAVE_STATE_SLOTS: u8 = 4;
+// The height of the menu bar at the top of the window
+const MENU_BAR_HEIGHT: i32 = 25;
+
+// Where the console device is mapped into the address space
+const CONSOLE_BASE: u16 = 0xF000;
+// Where the keyboard device is mapped into the address space
+const KEYBOARD_BASE: u16 = 0xF010;
+// The interrupt line the keyboard raises when a key is pressed
+const KEYBOARD_IRQ: u8 = 1;
+// Where the timer device is mapped into the address space
+const TIMER_BASE: u16 = 0xF020;
+// The interrupt line the timer raises when it expires
+const TIMER_IRQ: u8 = 0;
+// How long to wait for FLTK events before checking for console output
+const CONSOLE_POLL_SECS: f64 = 1.0 / 60.0;
+// How fast to run the virtual machine
+const CYCLES_PER_SECOND: f64 = 1000.0;
+// The number of cycles to run a ROM loaded from a file for
+const ROM_CYCLES: u64 = 100_000;
 
 mod emu;
 
 use crate::emu::EmulationHandler;
-use cjemu_runtime::cjemu_api::Opcode;
-use cjemu_runtime::CJEmuVirtualMachine;
+use cjemu_runtime::cjemu_api::{Instruction, Opcode, ReadableMemory, ADDRESS_SPACE};
+use cjemu_runtime::{
+    BusLayout, CJEmuVirtualMachine, Console, ConsoleEvent, Keyboard, KeyboardInput, MemoryBus, Ram,
+    Rom, RomImage, SaveState, Timer,
+};
 use directories::UserDirs;
 use fltk::app::App;
+use fltk::enums::{Event, Shortcut};
 use fltk::group::PackType;
+use fltk::menu::{MenuBar, MenuFlag};
 use fltk::text::{TextBuffer, TextEditor};
 use fltk::{app, enums::Font, group::Pack, prelude::*, window::DoubleWindow, window::Window};
 use std::fs::File;
-use std::io::Write;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 
 #[allow(dead_code)]
@@ -30,7 +58,36 @@ struct CJEmu {
     terminal_font: Font,
 
     memory_map_tmp: Option<TextEditor>,
-    console_tmp: Option<TextEditor>,
+    console: Option<TextEditor>,
+}
+
+// Options given on the command line
+struct Options {
+    // Skip the window entirely and use the terminal as the console
+    headless: bool,
+    // A program to run instead of the example program
+    rom_path: Option<PathBuf>,
+    // Where to write the contents of RAM once the program has run
+    ram_dump_path: Option<PathBuf>,
+}
+
+// The formats programs and memory contents are read and written in, chosen
+// by file extension
+#[derive(Copy, Clone)]
+enum FileFormat {
+    Image,
+    IntelHex,
+    SRecord,
+    Binary,
+}
+
+// Messages sent by the menus to the event loop
+#[derive(Copy, Clone)]
+enum Message {
+    // Save the state of the virtual machine into the numbered slot
+    SaveState(u8),
+    // Replace the state of the virtual machine with the numbered slot
+    LoadState(u8),
 }
 
 #[derive(Debug)]
@@ -38,22 +95,85 @@ struct CJEmuFiles {
     home_dir: PathBuf,
     cjemu_dir: PathBuf,
     extracted_font_path: PathBuf,
+    save_state_dir: PathBuf,
 }
 
 fn main() {
-    println!(
+    eprintln!(
         "starting {} v{}",
         env!("CARGO_PKG_NAME"),
         env!("CARGO_PKG_VERSION")
     );
 
+    let options = parse_options();
+    let (rom, cycles) = load_rom(options.rom_path.as_deref());
+
+    if options.headless {
+        run_headless(rom, cycles, options.ram_dump_path.as_deref());
+    } else {
+        run_gui(rom, cycles, options.ram_dump_path.as_deref());
+    }
+
+    eprintln!("exiting");
+}
+
+// Usage: cjemu [--headless] [--dump-ram PATH] [ROM]
+fn parse_options() -> Options {
+    let mut options = Options {
+        headless: false,
+        rom_path: None,
+        ram_dump_path: None,
+    };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--headless" => options.headless = true,
+            "--dump-ram" => {
+                let path = args.next().expect("missing path after --dump-ram");
+                options.ram_dump_path = Some(PathBuf::from(path));
+            }
+            option if option.starts_with("--") => panic!("unknown option {}", option),
+            _ => options.rom_path = Some(PathBuf::from(arg)),
+        }
+    }
+
+    options
+}
+
+fn run_headless(rom: Rom, cycles: u64, ram_dump_path: Option<&Path>) {
+    let (keyboard, keyboard_input) = Keyboard::channel(Some(KEYBOARD_IRQ));
+    let mut emulation_handler =
+        EmulationHandler::new(create_virtual_machine(rom, Console::stdout(), keyboard));
+    eprintln!("initialized virtual machine");
+
+    // Feed the keyboard from stdin for as long as it stays open
+    std::thread::spawn(move || {
+        for byte in std::io::stdin().bytes() {
+            match byte {
+                Ok(byte) => keyboard_input.press(byte),
+                Err(_) => break,
+            }
+        }
+    });
+
+    // Run the program, blocking until it finishes
+    emulation_handler.cycle(cycles, CYCLES_PER_SECOND);
+    emulation_handler.finish();
+
+    if let Some(path) = ram_dump_path {
+        dump_ram(&emulation_handler, path);
+    }
+}
+
+fn run_gui(rom: Rom, cycles: u64, ram_dump_path: Option<&Path>) {
     // Get file locations and directories
     let files = load_files();
-    println!("important file locations: {:#?}", files);
+    eprintln!("important file locations: {:#?}", files);
 
     // Create the wrapper application
     let app = app::App::default().with_scheme(app::Scheme::Plastic);
-    println!("initialized FLTK");
+    eprintln!("initialized FLTK");
 
     // Load the font (and extract FiraCode from the binary as the default if
     // necessary)
@@ -69,39 +189,251 @@ fn main() {
         terminal_font,
 
         memory_map_tmp: None,
-        console_tmp: None,
+        console: None,
     };
 
-    // Create the window
+    // Create the window, with menus sending messages to the event loop
+    let (sender, receiver) = app::channel::<Message>();
     create_window(
         &mut cjemu,
         concat!(env!("CARGO_PKG_NAME"), " v", env!("CARGO_PKG_VERSION")),
+        sender,
     );
-    println!("created window");
+    eprintln!("created window");
 
     // Show the window and start the app
     cjemu.window.expect("failed to load window").show();
-    println!("displayed window");
+    eprintln!("displayed window");
+
+    // The console device streams its output to the console pane, which in
+    // turn feeds key presses to the keyboard device
+    let (console, console_events) = Console::channel();
+    let (keyboard, keyboard_input) = Keyboard::channel(Some(KEYBOARD_IRQ));
+    let mut console_pane = cjemu.console.expect("failed to load console");
+    forward_keys(&mut console_pane, keyboard_input);
+    let mut emulation_handler =
+        EmulationHandler::new(create_virtual_machine(rom, console, keyboard));
+    eprintln!("initialized virtual machine");
+
+    // Run the program
+    emulation_handler.cycle(cycles, CYCLES_PER_SECOND);
+
+    // Run the event loop, blocking execution in the main thread until the
+    // app exits, and show console output as it arrives
+    let mut console_buffer = console_pane.buffer().expect("missing console buffer");
+    let mut console_text = String::new();
+    while app::wait_for(CONSOLE_POLL_SECS).expect("failed to wait for events") {
+        match receiver.recv() {
+            Some(Message::SaveState(slot)) => {
+                save_state(&emulation_handler, &cjemu.files.save_state_path(slot))
+            }
+            Some(Message::LoadState(slot)) => {
+                // Carry on from the loaded state with a fresh run, which
+                // replaces any run still going rather than queueing behind it,
+                // even if the program had already stopped
+                if load_state(&emulation_handler, &cjemu.files.save_state_path(slot)) {
+                    emulation_handler.cycle(cycles, CYCLES_PER_SECOND);
+                }
+            }
+            None => {}
+        }
+
+        let mut changed = false;
+        for event in console_events.try_iter() {
+            apply_console_event(&mut console_text, event);
+            changed = true;
+        }
+        if changed {
+            console_buffer.set_text(&console_text);
+        }
+    }
+
+    if let Some(path) = ram_dump_path {
+        dump_ram(&emulation_handler, path);
+    }
+}
 
-    // 50/50 split for rom/ram size (right now)
-    let virtual_machine = CJEmuVirtualMachine::new(2 << 15, 2 << 15);
-    let _emulation_handler = EmulationHandler::new(virtual_machine);
-    println!("initialized virtual machine");
+// Send typed and pasted text to the keyboard device instead of editing the
+// console pane
+fn forward_keys(console_pane: &mut TextEditor, keyboard_input: KeyboardInput) {
+    console_pane.handle(move |_, event| match event {
+        Event::KeyDown => {
+            let text = app::event_text();
+            match text.chars().next() {
+                // Enter, backspace, tab and escape are keys too, but other
+                // control characters come from shortcuts such as Ctrl+V, which
+                // the pane turns into a paste event
+                Some(c) if !c.is_control() || "\r\x08\t\x1b".contains(c) => {
+                    keyboard_input.type_text(&text);
+                    true
+                }
+                _ => false,
+            }
+        }
+        Event::Paste => {
+            keyboard_input.type_text(&app::event_text());
+            true
+        }
+        _ => false,
+    });
+}
+
+// Load the program at `rom_path`, or the example program without one, along
+// with the number of cycles to run it for
+fn load_rom(rom_path: Option<&Path>) -> (Rom, u64) {
+    let path = match rom_path {
+        Some(path) => path,
+        None => return example_rom(),
+    };
 
-    let _example_program: Vec<u8> = vec![
+    let bytes = std::fs::read(path).unwrap_or_else(|_| panic!("failed to read ROM at {:?}", path));
+    let text =
+        || std::str::from_utf8(&bytes).unwrap_or_else(|_| panic!("ROM at {:?} isn't text", path));
+    let image = match FileFormat::of(path) {
+        FileFormat::Image => RomImage::parse(&bytes).map_err(|err| err.to_string()),
+        FileFormat::IntelHex => RomImage::from_intel_hex(text()).map_err(|err| err.to_string()),
+        FileFormat::SRecord => RomImage::from_srecord(text()).map_err(|err| err.to_string()),
+        // Raw binaries start at the beginning of ROM
+        FileFormat::Binary => Ok(RomImage::from_binary(bytes.clone(), 0)),
+    }
+    .unwrap_or_else(|err| panic!("failed to load ROM at {:?}: {}", path, err));
+    let rom = Rom::from_image(&image)
+        .unwrap_or_else(|err| panic!("failed to load ROM at {:?}: {}", path, err));
+    eprintln!("loaded ROM from {:?}", path);
+
+    (rom, ROM_CYCLES)
+}
+
+// Write the whole of RAM to `path`, with addresses relative to the start of
+// RAM
+fn dump_ram(emulation_handler: &EmulationHandler, path: &Path) {
+    let image = {
+        let virtual_machine = emulation_handler.virtual_machine();
+        let ram = virtual_machine.bus().ram();
+        RomImage::from_memory(ram, 0, ram.size())
+    };
+
+    let bytes = match FileFormat::of(path) {
+        FileFormat::Image => image.to_bytes(),
+        FileFormat::IntelHex => image.to_intel_hex().map(String::into_bytes),
+        FileFormat::SRecord => image.to_srecord().map(String::into_bytes),
+        FileFormat::Binary => Ok(image.segments[0].data.clone()),
+    }
+    .unwrap_or_else(|err| panic!("failed to convert RAM dump: {}", err));
+    std::fs::write(path, bytes)
+        .unwrap_or_else(|_| panic!("failed to write RAM dump to {:?}", path));
+    eprintln!("dumped RAM to {:?}", path);
+}
+
+// Write the state of the virtual machine to `path`, reporting failures rather
+// than giving up on the running program
+fn save_state(emulation_handler: &EmulationHandler, path: &Path) {
+    let bytes = emulation_handler.virtual_machine().save_state().to_bytes();
+
+    let written = path
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|_| std::fs::write(path, bytes));
+    match written {
+        Ok(()) => eprintln!("saved state to {:?}", path),
+        Err(err) => eprintln!("failed to save state to {:?}: {}", path, err),
+    }
+}
+
+// Replace the state of the virtual machine with the one saved at `path`,
+// returning whether it was loaded
+fn load_state(emulation_handler: &EmulationHandler, path: &Path) -> bool {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("failed to read state from {:?}: {}", path, err);
+            return false;
+        }
+    };
+
+    let loaded = SaveState::parse(&bytes)
+        .and_then(|state| emulation_handler.virtual_machine().load_state(&state));
+    match loaded {
+        Ok(()) => {
+            eprintln!("loaded state from {:?}", path);
+            true
+        }
+        Err(err) => {
+            eprintln!("failed to load state from {:?}: {}", path, err);
+            false
+        }
+    }
+}
+
+impl FileFormat {
+    fn of(path: &Path) -> Self {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("hex") | Some("ihex") => Self::IntelHex,
+            Some("srec") | Some("s19") | Some("mot") => Self::SRecord,
+            Some("bin") => Self::Binary,
+            _ => Self::Image,
+        }
+    }
+}
+
+// The example program runs straight through, so it takes as many cycles as its
+// instructions add up to
+fn example_rom() -> (Rom, u64) {
+    let mut example_program: Vec<u8> = Vec::new();
+    // Print a greeting through the console
+    for &c in b"hi\n" {
+        example_program.extend(&[Opcode::LdA8 as u8, c, Opcode::StA16 as u8]);
+        example_program.extend(&CONSOLE_BASE.to_le_bytes());
+    }
+    example_program.extend(&[
         Opcode::LdA8 as u8,
         15u8,
         Opcode::LdB8 as u8,
         32u8,
         Opcode::Add as u8,
-        // 47 should be found in the `A` register and the ALU outputs
-    ];
+        // Exit with the 47 found in the `A` register
+        Opcode::Halt as u8,
+    ]);
+
+    // The ROM holds only the example program, starting at the beginning
+    let image = RomImage::new(0, 0).with_segment(0, example_program);
+    let rom = Rom::from_image(&image).expect("failed to create example ROM");
+
+    let mut cycles = 0;
+    let mut address = 0;
+    while (address as u32) < rom.size() {
+        let instruction = Instruction::decode(&rom, address).expect("invalid example program");
+        cycles += instruction.opcode().cycles() as u64;
+        address += instruction.size();
+    }
 
-    // Start the event loop, blocking execution in the main thread until the
-    // app exits
-    app.run().unwrap();
+    (rom, cycles)
+}
 
-    println!("exiting");
+fn create_virtual_machine(rom: Rom, console: Console, keyboard: Keyboard) -> CJEmuVirtualMachine {
+    let layout = BusLayout::default()
+        .with_device(CONSOLE_BASE, Box::new(console))
+        .with_device(KEYBOARD_BASE, Box::new(keyboard))
+        .with_device(TIMER_BASE, Box::new(Timer::new(Some(TIMER_IRQ))));
+    // RAM fills the upper half of the address space in the default layout
+    let ram = Ram::new(0, ADDRESS_SPACE / 2).expect("failed to create RAM");
+    let bus = MemoryBus::new(rom, ram, layout).expect("failed to lay out memory");
+    CJEmuVirtualMachine::new(bus)
+}
+
+fn apply_console_event(console_text: &mut String, event: ConsoleEvent) {
+    match event {
+        ConsoleEvent::Print(c) => console_text.push(c),
+        ConsoleEvent::Newline => console_text.push('\n'),
+        ConsoleEvent::Backspace => {
+            console_text.pop();
+        }
+        ConsoleEvent::Clear => console_text.clear(),
+    }
 }
 
 fn load_files() -> CJEmuFiles {
@@ -121,11 +453,26 @@ fn load_files() -> CJEmuFiles {
         efp
     };
 
+    // Save state directory (within `cjemu` directory)
+    let save_state_dir = {
+        let mut ssd = PathBuf::from(&cjemu_dir);
+        ssd.push(SAVE_STATE_DIR_REL);
+        ssd
+    };
+
     // Wrap the files into a neat little struct
     CJEmuFiles {
         home_dir,
         cjemu_dir,
         extracted_font_path,
+        save_state_dir,
+    }
+}
+
+impl CJEmuFiles {
+    // The file holding the save state in the numbered slot
+    fn save_state_path(&self, slot: u8) -> PathBuf {
+        self.save_state_dir.join(format!("slot{}.cjss", slot))
     }
 }
 
@@ -134,7 +481,7 @@ fn load_font(app: &app::App, default_font: &[u8], font_file_loc: &Path) -> Font
         // Write the font from the binary into the output file if it
         // doesn't exist
         if !font_file_loc.exists() {
-            println!(
+            eprintln!(
                 "font not found at {:?}, extracting the default packaged font (FiraCode)",
                 font_file_loc
             );
@@ -159,22 +506,23 @@ fn load_font(app: &app::App, default_font: &[u8], font_file_loc: &Path) -> Font
                 .write_all(default_font)
                 .unwrap_or_else(|_| panic!("failed to write font file at {:?}", font_file_loc));
 
-            println!("extracted font");
+            eprintln!("extracted font");
         }
 
         app.load_font(font_file_loc)
             .unwrap_or_else(|_| panic!("failed to load font at {:?}", font_file_loc))
     };
-    println!("loaded font by name {}", font_name);
+    eprintln!("loaded font by name {}", font_name);
 
     Font::by_name(font_name)
 }
 
-fn create_window(cjemu: &mut CJEmu, title: &'static str) {
+fn create_window(cjemu: &mut CJEmu, title: &'static str, sender: app::Sender<Message>) {
     // Create the window
-    let mut wind = Window::new(0, 0, 150, 100, title).center_screen();
+    let mut wind = Window::new(0, 0, 150, 100 + MENU_BAR_HEIGHT, title).center_screen();
     wind.make_resizable(true);
 
+    create_menu_bar(sender);
     create_outer_pack(cjemu, &wind);
 
     // Finish window creation
@@ -182,9 +530,37 @@ fn create_window(cjemu: &mut CJEmu, title: &'static str) {
     cjemu.window = Some(wind);
 }
 
+fn create_menu_bar(sender: app::Sender<Message>) -> MenuBar {
+    // Create the menu bar across the top of the window
+    let mut menu_bar = MenuBar::new(0, 0, 150, MENU_BAR_HEIGHT, "");
+
+    // Slots are saved with Ctrl and their number, and loaded with Alt
+    for slot in 1..=SAVE_STATE_SLOTS {
+        let key = (b'0' + slot) as char;
+        menu_bar.add_emit(
+            &format!("State/Save Slot {}", slot),
+            Shortcut::Ctrl | key,
+            MenuFlag::Normal,
+            sender,
+            Message::SaveState(slot),
+        );
+        menu_bar.add_emit(
+            &format!("State/Load Slot {}", slot),
+            Shortcut::Alt | key,
+            MenuFlag::Normal,
+            sender,
+            Message::LoadState(slot),
+        );
+    }
+
+    menu_bar
+}
+
 fn create_outer_pack(cjemu: &mut CJEmu, window: &Window) -> Pack {
-    // Create the master horizontal pack
-    let mut outer_pack = Pack::default().with_size(150, 100).center_of(window);
+    // Create the master horizontal pack below the menu bar
+    let mut outer_pack = Pack::default()
+        .with_size(150, 100)
+        .with_pos(0, MENU_BAR_HEIGHT);
     outer_pack.set_spacing(10);
     outer_pack.set_type(PackType::Horizontal);
     window.resizable(&outer_pack);
@@ -225,13 +601,9 @@ fn create_right_pack(cjemu: &mut CJEmu) -> Pack {
     let mut right_pack = Pack::default().with_size(75, 100);
     right_pack.set_type(PackType::Vertical);
 
-    cjemu.console_tmp = Some({
+    cjemu.console = Some({
         let mut editor = TextEditor::new(0, 0, 75, 100, "");
-        editor.set_buffer(Some({
-            let mut buf = TextBuffer::default();
-            buf.append("console!");
-            buf
-        }));
+        editor.set_buffer(Some(TextBuffer::default()));
         editor.set_text_font(cjemu.terminal_font);
         right_pack.resizable(&editor);
         editor